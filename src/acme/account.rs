@@ -1,9 +1,15 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use instant_acme::{Account, NewAccount};
+use tokio::sync::RwLock;
 use tokio::time::{Duration, sleep};
 use tracing::{info, warn};
 
-use crate::error::Result;
+use crate::config::{IssuerConfig, OrderRateLimit};
+use crate::error::{Error, Result};
 
+use super::rate_limit::TokenBucket;
 use super::storage::CertificateStorage;
 
 /// Manages ACME account creation and restoration
@@ -14,17 +20,19 @@ impl AcmeAccount {
     /// Retries on connection failure to handle ACME server startup delays
     pub async fn load_or_create(
         storage: &CertificateStorage,
+        issuer: &str,
         directory_url: &str,
     ) -> Result<Account> {
         const MAX_RETRIES: u32 = 5;
         const INITIAL_DELAY_MS: u64 = 1000;
 
         for attempt in 1..=MAX_RETRIES {
-            match Self::try_load_or_create(storage, directory_url).await {
+            match Self::try_load_or_create(storage, issuer, directory_url).await {
                 Ok(account) => return Ok(account),
                 Err(e) if attempt < MAX_RETRIES => {
                     let delay = INITIAL_DELAY_MS * 2_u64.pow(attempt - 1);
                     warn!(
+                        issuer,
                         attempt,
                         max_retries = MAX_RETRIES,
                         delay_ms = delay,
@@ -42,32 +50,126 @@ impl AcmeAccount {
 
     async fn try_load_or_create(
         storage: &CertificateStorage,
+        issuer: &str,
         directory_url: &str,
     ) -> Result<Account> {
         // Try to load existing account
-        if let Some(credentials) = storage.load_account().await? {
-            info!("Restoring existing ACME account");
-            let account = Account::from_credentials(credentials).await?;
+        if let Some(credentials) = storage.load_account(issuer).await? {
+            info!(issuer, "Restoring existing ACME account");
+            let account = Account::builder()?.from_credentials(credentials).await?;
             return Ok(account);
         }
 
         // Create new account
-        info!("Creating new ACME account");
-        let (account, credentials) = Account::create(
-            &NewAccount {
-                contact: &[],
-                terms_of_service_agreed: true,
-                only_return_existing: false,
-            },
-            directory_url,
-            None,
-        )
-        .await?;
+        info!(issuer, directory_url, "Creating new ACME account");
+        let (account, credentials) = Account::builder()?
+            .create(
+                &NewAccount {
+                    contact: &[],
+                    terms_of_service_agreed: true,
+                    only_return_existing: false,
+                },
+                directory_url.to_string(),
+                None,
+            )
+            .await?;
 
         // Save credentials
-        storage.save_account(&credentials).await?;
-        info!("ACME account created and saved");
+        storage.save_account(issuer, &credentials).await?;
+        info!(issuer, "ACME account created and saved");
 
         Ok(account)
     }
 }
+
+/// An issuer's ACME account along with its new order rate limit
+#[derive(Clone)]
+pub struct IssuerAccount {
+    /// Issuer name, also naming the stored account
+    pub name: String,
+    pub account: Arc<RwLock<Account>>,
+    pub new_orders: Arc<TokenBucket>,
+    pub directory_url: String,
+    /// Account with the issuer's preflight directory, if configured
+    pub preflight: Option<Box<IssuerAccount>>,
+}
+
+impl IssuerAccount {
+    async fn load_or_create(
+        storage: &CertificateStorage,
+        name: String,
+        directory_url: &str,
+        rate_limit: &OrderRateLimit,
+    ) -> Result<Self> {
+        let account = AcmeAccount::load_or_create(storage, &name, directory_url).await?;
+        Ok(Self {
+            name,
+            account: Arc::new(RwLock::new(account)),
+            new_orders: Arc::new(TokenBucket::new(rate_limit.burst, rate_limit.per_hour)),
+            directory_url: directory_url.to_string(),
+            preflight: None,
+        })
+    }
+}
+
+/// ACME accounts for every configured issuer, keyed by issuer name
+#[derive(Clone, Default)]
+pub struct IssuerAccounts {
+    accounts: HashMap<String, IssuerAccount>,
+}
+
+impl IssuerAccounts {
+    /// Load or create an account for each issuer
+    pub async fn load_or_create(
+        storage: &CertificateStorage,
+        issuers: &[IssuerConfig],
+        rate_limit: &OrderRateLimit,
+    ) -> Result<Self> {
+        let mut accounts = HashMap::new();
+
+        for issuer in issuers {
+            let mut account = IssuerAccount::load_or_create(
+                storage,
+                issuer.name.clone(),
+                &issuer.directory_url,
+                rate_limit,
+            )
+            .await?;
+            if let Some(preflight_url) = &issuer.preflight_directory_url {
+                let preflight = IssuerAccount::load_or_create(
+                    storage,
+                    format!("{}.preflight", issuer.name),
+                    preflight_url,
+                    rate_limit,
+                )
+                .await?;
+                account.preflight = Some(Box::new(preflight));
+            }
+            accounts.insert(issuer.name.clone(), account);
+        }
+
+        Ok(Self { accounts })
+    }
+
+    /// Find an issuer's account, or the preflight account named `<issuer>.preflight`
+    pub fn find(&self, name: &str) -> Option<&IssuerAccount> {
+        self.accounts.values().find_map(|account| {
+            if account.name == name {
+                Some(account)
+            } else {
+                account
+                    .preflight
+                    .as_deref()
+                    .filter(|preflight| preflight.name == name)
+            }
+        })
+    }
+
+    /// Get the account for an issuer
+    pub fn get(&self, issuer: &str) -> Result<IssuerAccount> {
+        self.accounts
+            .get(issuer)
+            .cloned()
+            .ok_or_else(|| Error::Config(format!("No ACME account for issuer '{issuer}'")))
+    }
+}
@@ -1,44 +1,297 @@
 use std::collections::HashMap;
+use std::net::Ipv6Addr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use crate::error::{Error, Result};
+use crate::xds::XdsState;
+
+/// Timeout for a single self-check request
+const SELF_CHECK_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay between self-check attempts
+const SELF_CHECK_RETRY_DELAY: Duration = Duration::from_millis(500);
 
 /// Represents an active HTTP-01 challenge
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActiveChallenge {
     pub token: String,
     pub key_authorization: String,
     pub cert_name: String,
+    /// Identifier being validated, used as the Host header for self-checks
+    pub identifier: String,
 }
 
 /// Thread-safe state for tracking active ACME challenges
 #[derive(Debug, Clone, Default)]
 pub struct ChallengeState {
-    inner: Arc<RwLock<HashMap<String, ActiveChallenge>>>,
+    inner: Arc<RwLock<Challenges>>,
+}
+
+#[derive(Debug, Default)]
+struct Challenges {
+    by_token: HashMap<String, ActiveChallenge>,
+    /// Incremented on every change, so served listeners can be matched to a challenge set
+    generation: u64,
 }
 
 impl ChallengeState {
     pub fn new() -> Self {
-        Self {
-            inner: Arc::new(RwLock::new(HashMap::new())),
-        }
+        Self::default()
     }
 
     /// Add a new active challenge
     pub async fn add(&self, challenge: ActiveChallenge) {
         let mut state = self.inner.write().await;
-        state.insert(challenge.token.clone(), challenge);
+        state.by_token.insert(challenge.token.clone(), challenge);
+        state.generation += 1;
     }
 
-    /// Get all active challenges
-    pub async fn get_all(&self) -> Vec<ActiveChallenge> {
+    /// Get all active challenges along with the generation they belong to
+    pub async fn snapshot(&self) -> (u64, Vec<ActiveChallenge>) {
         let state = self.inner.read().await;
-        state.values().cloned().collect()
+        (state.generation, state.by_token.values().cloned().collect())
+    }
+
+    /// Key authorization for a challenge token, if the challenge is active
+    pub async fn key_authorization(&self, token: &str) -> Option<String> {
+        let state = self.inner.read().await;
+        state
+            .by_token
+            .get(token)
+            .map(|challenge| challenge.key_authorization.clone())
+    }
+
+    /// Current generation of the challenge set
+    pub async fn generation(&self) -> u64 {
+        self.inner.read().await.generation
     }
 
     /// Clear all challenges for a specific certificate
     pub async fn clear_for_cert(&self, cert_name: &str) {
         let mut state = self.inner.write().await;
-        state.retain(|_, v| v.cert_name != cert_name);
+        let before = state.by_token.len();
+        state.by_token.retain(|_, v| v.cert_name != cert_name);
+        if state.by_token.len() != before {
+            state.generation += 1;
+        }
+    }
+}
+
+/// Publishes challenge responses through Envoy and confirms they are served
+#[derive(Clone)]
+pub struct ChallengePublisher {
+    xds_state: Arc<XdsState>,
+    propagation_timeout: Duration,
+    self_check_address: Option<String>,
+    /// Challenges are also answered by the built-in responder
+    standalone_responder: bool,
+}
+
+impl ChallengePublisher {
+    pub fn new(
+        xds_state: Arc<XdsState>,
+        propagation_timeout: Duration,
+        self_check_address: Option<String>,
+        standalone_responder: bool,
+    ) -> Self {
+        Self {
+            xds_state,
+            propagation_timeout,
+            self_check_address,
+            standalone_responder,
+        }
+    }
+
+    /// Trigger a listener rebuild after the challenge set changed
+    pub fn notify(&self) {
+        self.xds_state.notify_change();
+    }
+
+    /// Wait until the challenge set of `generation` is served by Envoy
+    ///
+    /// Every connected LDS stream must have ACKed a listener version containing the
+    /// challenge routes. With the built-in responder, no ACK is needed while no Envoy is
+    /// connected. If a self-check address is configured, each challenge is then
+    /// fetched through it and compared with the expected key authorization.
+    pub async fn wait_until_served(
+        &self,
+        generation: u64,
+        challenges: &[ActiveChallenge],
+    ) -> Result<()> {
+        let deadline = Instant::now() + self.propagation_timeout;
+
+        if self.standalone_responder && self.xds_state.lds_stream_count() == 0 {
+            // The built-in responder answers as soon as a challenge is added
+            debug!(
+                generation,
+                "No Envoy connected, challenges served by built-in responder"
+            );
+        } else {
+            let Some(version) = self
+                .xds_state
+                .wait_for_challenge_ack(generation, deadline)
+                .await
+            else {
+                return Err(Error::ChallengeFailed(format!(
+                    "Challenge routes were not acknowledged by Envoy within {:?}",
+                    self.propagation_timeout
+                )));
+            };
+            debug!(
+                generation,
+                version, "Challenge routes acknowledged by Envoy"
+            );
+        }
+
+        if let Some(address) = &self.self_check_address {
+            for challenge in challenges {
+                self_check(address, challenge, deadline).await?;
+            }
+            info!(
+                address,
+                num_challenges = challenges.len(),
+                "Challenge self-check passed"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Fetch a challenge response through Envoy until it matches or the deadline passes
+async fn self_check(address: &str, challenge: &ActiveChallenge, deadline: Instant) -> Result<()> {
+    let url = format!(
+        "http://{address}/.well-known/acme-challenge/{}",
+        challenge.token
+    );
+
+    loop {
+        let error = match fetch(&url, &host_header(&challenge.identifier)).await {
+            Ok(body) if body == challenge.key_authorization.as_bytes() => return Ok(()),
+            Ok(_) => "unexpected response body".to_string(),
+            Err(e) => e,
+        };
+
+        if Instant::now() + SELF_CHECK_RETRY_DELAY >= deadline {
+            return Err(Error::ChallengeFailed(format!(
+                "Self-check of {} via {url} failed: {error}",
+                challenge.identifier
+            )));
+        }
+        debug!(
+            identifier = challenge.identifier,
+            url, error, "Challenge self-check failed, retrying"
+        );
+        tokio::time::sleep(SELF_CHECK_RETRY_DELAY).await;
+    }
+}
+
+/// Host header for an identifier, with IPv6 addresses in brackets
+fn host_header(identifier: &str) -> String {
+    match identifier.parse::<Ipv6Addr>() {
+        Ok(ip) => format!("[{ip}]"),
+        Err(_) => identifier.to_string(),
+    }
+}
+
+/// GET `url` with the given Host header, returning the body of a successful response
+async fn fetch(url: &str, host: &str) -> std::result::Result<Vec<u8>, String> {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let request = hyper::Request::get(url)
+        .header(hyper::header::HOST, host)
+        .body(Empty::new())
+        .map_err(|e| format!("invalid request: {e}"))?;
+
+    let response = tokio::time::timeout(SELF_CHECK_REQUEST_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| "request timed out".to_string())?
+        .map_err(|e| format!("request failed: {e}"))?;
+
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| format!("failed to read body: {e}"))?;
+
+    Ok(body.to_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(cert_name: &str, token: &str) -> ActiveChallenge {
+        ActiveChallenge {
+            token: token.to_string(),
+            key_authorization: format!("{token}.thumbprint"),
+            cert_name: cert_name.to_string(),
+            identifier: format!("{cert_name}.example.com"),
+        }
+    }
+
+    /// Serve the current challenge set and ACK it, as the listener updater and Envoy do
+    async fn serve(xds_state: &XdsState, challenge_state: &ChallengeState, stream: u64) {
+        let (generation, _) = challenge_state.snapshot().await;
+        xds_state.update_listeners(Vec::new()).await;
+        xds_state.mark_challenges_applied(generation);
+        let version = xds_state.version_info().await.parse().unwrap();
+        xds_state.record_lds_ack(stream, version);
+    }
+
+    #[test]
+    fn test_host_header() {
+        assert_eq!(host_header("example.com"), "example.com");
+        assert_eq!(host_header("192.0.2.10"), "192.0.2.10");
+        assert_eq!(host_header("2001:db8::1"), "[2001:db8::1]");
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_orders() {
+        let xds_state = XdsState::new();
+        let stream = xds_state.register_lds_stream();
+        let challenge_state = ChallengeState::new();
+        let publisher =
+            ChallengePublisher::new(xds_state.clone(), Duration::from_millis(100), None, false);
+
+        challenge_state.add(challenge("a", "token-a")).await;
+        let b = challenge("b", "token-b");
+        challenge_state.add(b.clone()).await;
+        let generation_b = challenge_state.generation().await;
+
+        // Order a completes while order b still waits for its routes to be acknowledged
+        challenge_state.clear_for_cert("a").await;
+        let (generation, challenges) = challenge_state.snapshot().await;
+        assert!(generation > generation_b);
+        assert_eq!(challenges.len(), 1);
+        assert_eq!(challenge_state.key_authorization("token-a").await, None);
+        assert_eq!(
+            challenge_state.key_authorization("token-b").await,
+            Some(b.key_authorization.clone())
+        );
+
+        // Listeners built from the later generation still serve b's challenge
+        serve(&xds_state, &challenge_state, stream).await;
+        publisher
+            .wait_until_served(generation_b, std::slice::from_ref(&b))
+            .await
+            .unwrap();
+
+        // Clearing a certificate without challenges does not start a new generation
+        challenge_state.clear_for_cert("a").await;
+        assert_eq!(challenge_state.generation().await, generation);
     }
 }
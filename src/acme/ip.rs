@@ -0,0 +1,30 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/// Documentation addresses used to select the outbound source address
+///
+/// Connecting a UDP socket only consults the routing table, so nothing is sent to them.
+const PROBES: [(IpAddr, IpAddr); 2] = [
+    (
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+    ),
+    (
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+    ),
+];
+
+/// Detect the addresses this host uses for outbound IPv4 and IPv6 traffic
+///
+/// Address families without a default route are skipped.
+pub fn detect_ip_addresses() -> Vec<IpAddr> {
+    PROBES
+        .iter()
+        .filter_map(|(bind, target)| {
+            let socket = UdpSocket::bind(SocketAddr::new(*bind, 0)).ok()?;
+            socket.connect(SocketAddr::new(*target, 53)).ok()?;
+            let ip = socket.local_addr().ok()?.ip();
+            (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+        })
+        .collect()
+}
@@ -1,10 +1,18 @@
 mod account;
 mod challenge;
+mod ip;
+mod ocsp;
 mod order;
+mod rate_limit;
 mod renewal;
+mod responder;
+mod static_certs;
+mod status;
 mod storage;
 
-pub use account::AcmeAccount;
-pub use challenge::ChallengeState;
+pub use account::IssuerAccounts;
+pub use challenge::{ActiveChallenge, ChallengePublisher, ChallengeState};
 pub use renewal::RenewalManager;
+pub use responder::ChallengeResponder;
+pub use static_certs::StaticCertificates;
 pub use storage::CertificateStorage;
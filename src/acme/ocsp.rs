@@ -0,0 +1,607 @@
+/// OCSP response fetching and validation for stapling (RFC 6960)
+///
+/// Requests and responses are encoded and parsed with the ASN.1 types x509-parser
+/// re-exports; only the fields needed for validation are extracted.
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use ring::digest::{SHA1_FOR_LEGACY_USE_ONLY, digest};
+use x509_parser::der_parser::asn1_rs::{
+    self, Any, BitString, Class, Enumerated, GeneralizedTime, Integer, Null, OctetString, Oid,
+    OptTaggedParser, ParseResult, Sequence, Tag, ToDer,
+};
+use x509_parser::der_parser::oid;
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::nom::combinator::{all_consuming, complete, consumed};
+use x509_parser::nom::multi::many0;
+use x509_parser::oid_registry::{OID_HASH_SHA1, OID_PKIX_ACCESS_DESCRIPTOR_OCSP};
+use x509_parser::pem::Pem;
+use x509_parser::prelude::*;
+
+use crate::error::{Error, Result};
+
+/// id-pkix-ocsp-basic (1.3.6.1.5.5.7.48.1.1)
+const OID_PKIX_OCSP_BASIC: Oid<'static> = oid!(1.3.6.1.5.5.7.48.1.1);
+
+/// Tolerated clock skew between us and the OCSP responder
+const CLOCK_SKEW_SECS: i64 = 300;
+
+/// Timeout for a request to the OCSP responder
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A validated OCSP response for a certificate
+#[derive(Debug, Clone)]
+pub struct OcspResponse {
+    /// DER-encoded OCSPResponse, as stapled by Envoy
+    pub der: Vec<u8>,
+    pub this_update: DateTime<Utc>,
+    pub next_update: Option<DateTime<Utc>>,
+}
+
+impl OcspResponse {
+    /// Whether the response is past the halfway point of its validity window
+    ///
+    /// Responses without `nextUpdate` are refreshed daily.
+    pub fn needs_refresh(&self, now: DateTime<Utc>) -> bool {
+        let refresh_at = match self.next_update {
+            Some(next_update) => self.this_update + (next_update - self.this_update) / 2,
+            None => self.this_update + chrono::Duration::days(1),
+        };
+        now >= refresh_at
+    }
+}
+
+/// Fetch and validate an OCSP response for the leaf of a certificate chain
+pub async fn fetch(cert_chain_pem: &str) -> Result<OcspResponse> {
+    let chain = parse_chain(cert_chain_pem)?;
+    let (leaf, issuer) = chain.certificates()?;
+
+    let url = responder_url(&leaf)?;
+    let request = build_request(&leaf, &issuer);
+    let der = post(&url, request).await?;
+
+    validate(der, &leaf, &issuer, Utc::now())
+}
+
+/// Re-validate a stored OCSP response against its certificate chain
+pub fn validate_stored(
+    der: Vec<u8>,
+    cert_chain_pem: &str,
+    now: DateTime<Utc>,
+) -> Result<OcspResponse> {
+    let chain = parse_chain(cert_chain_pem)?;
+    let (leaf, issuer) = chain.certificates()?;
+    validate(der, &leaf, &issuer, now)
+}
+
+/// DER blocks of a PEM certificate chain
+struct Chain(Vec<Pem>);
+
+impl Chain {
+    /// Parse the leaf certificate and its issuer
+    fn certificates(&self) -> Result<(X509Certificate<'_>, X509Certificate<'_>)> {
+        fn parse(pem: &Pem) -> Result<X509Certificate<'_>> {
+            X509Certificate::from_der(&pem.contents)
+                .map(|(_, cert)| cert)
+                .map_err(|e| Error::X509Parse {
+                    source: X509Error::from(e),
+                })
+        }
+
+        match self.0.as_slice() {
+            [leaf, issuer, ..] => Ok((parse(leaf)?, parse(issuer)?)),
+            _ => Err(Error::Ocsp(
+                "Certificate chain does not include the issuer certificate".to_string(),
+            )),
+        }
+    }
+}
+
+fn parse_chain(cert_chain_pem: &str) -> Result<Chain> {
+    Pem::iter_from_buffer(cert_chain_pem.as_bytes())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map(Chain)
+        .map_err(|e| Error::Ocsp(format!("Failed to parse certificate chain: {e}")))
+}
+
+/// Find the OCSP responder URL in the certificate's Authority Information Access extension
+fn responder_url(cert: &X509Certificate<'_>) -> Result<String> {
+    cert.extensions()
+        .iter()
+        .filter_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => Some(aia),
+            _ => None,
+        })
+        .flat_map(|aia| aia.iter())
+        .find_map(|desc| match &desc.access_location {
+            GeneralName::URI(uri) if desc.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP => {
+                Some(uri.to_string())
+            }
+            _ => None,
+        })
+        .ok_or_else(|| Error::Ocsp("Certificate has no OCSP responder URL".to_string()))
+}
+
+/// Build the CertID identifying `leaf` (SHA-1 hashes, as required of basic responders)
+fn cert_id(leaf: &X509Certificate<'_>, issuer: &X509Certificate<'_>) -> Vec<u8> {
+    let name_hash = digest(&SHA1_FOR_LEGACY_USE_ONLY, leaf.issuer().as_raw());
+    let key_hash = digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        &issuer.public_key().subject_public_key.data,
+    );
+
+    der_sequence(&[
+        &der_sequence(&[&to_der(OID_HASH_SHA1), &to_der(Null::new())]),
+        &to_der(OctetString::new(name_hash.as_ref())),
+        &to_der(OctetString::new(key_hash.as_ref())),
+        &to_der(Integer::new(leaf.raw_serial())),
+    ])
+}
+
+/// Build a DER-encoded OCSPRequest for a single certificate without a nonce
+fn build_request(leaf: &X509Certificate<'_>, issuer: &X509Certificate<'_>) -> Vec<u8> {
+    let request = der_sequence(&[&cert_id(leaf, issuer)]);
+    let request_list = der_sequence(&[&request]);
+    let tbs_request = der_sequence(&[&request_list]);
+    der_sequence(&[&tbs_request])
+}
+
+/// POST an OCSP request to the responder and return the response body
+async fn post(url: &str, request: Vec<u8>) -> Result<Vec<u8>> {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+    let request = hyper::Request::post(url)
+        .header(hyper::header::CONTENT_TYPE, "application/ocsp-request")
+        .body(Full::new(Bytes::from(request)))
+        .map_err(|e| Error::Ocsp(format!("Invalid OCSP responder URL {url}: {e}")))?;
+
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| Error::Ocsp(format!("OCSP request to {url} timed out")))?
+        .map_err(|e| Error::Ocsp(format!("OCSP request to {url} failed: {e}")))?;
+
+    if !response.status().is_success() {
+        return Err(Error::Ocsp(format!(
+            "OCSP responder {url} returned HTTP {}",
+            response.status()
+        )));
+    }
+
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| Error::Ocsp(format!("Failed to read OCSP response from {url}: {e}")))?;
+
+    Ok(body.to_bytes().to_vec())
+}
+
+/// Validate a DER-encoded OCSPResponse for `leaf`
+///
+/// Checks that the response is successful, signed by the issuer or a delegated responder,
+/// covers this certificate, reports it as good and is current.
+fn validate(
+    der: Vec<u8>,
+    leaf: &X509Certificate<'_>,
+    issuer: &X509Certificate<'_>,
+    now: DateTime<Utc>,
+) -> Result<OcspResponse> {
+    let (_, (status, response_bytes)) = all_consuming(parse_response)(&der).map_err(malformed)?;
+    if status.0 != 0 {
+        return Err(Error::Ocsp(format!(
+            "OCSP responder returned status {}",
+            status.0
+        )));
+    }
+
+    let response_bytes = response_bytes
+        .ok_or_else(|| Error::Ocsp("OCSP response has no response bytes".to_string()))?;
+    if response_bytes.response_type != OID_PKIX_OCSP_BASIC {
+        return Err(Error::Ocsp("Unsupported OCSP response type".to_string()));
+    }
+
+    let (_, basic) =
+        all_consuming(parse_basic_response)(response_bytes.response).map_err(malformed)?;
+    let (_, signature_algorithm) = AlgorithmIdentifier::from_der(basic.signature_algorithm)
+        .map_err(|_| Error::Ocsp("Invalid OCSP signature algorithm".to_string()))?;
+
+    verify_signer(
+        issuer,
+        basic.certs,
+        &signature_algorithm,
+        &basic.signature,
+        basic.tbs_raw,
+        now,
+    )?;
+
+    let expected_cert_id = cert_id(leaf, issuer);
+    let single = basic
+        .responses
+        .into_iter()
+        .find(|single| single.cert_id == expected_cert_id.as_slice())
+        .ok_or_else(|| Error::Ocsp("OCSP response does not cover the certificate".to_string()))?;
+
+    match single.status {
+        CertStatus::Good => {}
+        CertStatus::Revoked => {
+            return Err(Error::Ocsp("Certificate has been revoked".to_string()));
+        }
+        CertStatus::Unknown => {
+            return Err(Error::Ocsp("Certificate status is unknown".to_string()));
+        }
+    }
+
+    let this_update = to_datetime(&single.this_update)?;
+    let next_update = single.next_update.as_ref().map(to_datetime).transpose()?;
+
+    let skew = chrono::Duration::seconds(CLOCK_SKEW_SECS);
+    if this_update > now + skew {
+        return Err(Error::Ocsp(format!(
+            "OCSP response is not yet valid (thisUpdate {this_update})"
+        )));
+    }
+    if let Some(next_update) = next_update
+        && next_update < now
+    {
+        return Err(Error::Ocsp(format!(
+            "OCSP response has expired (nextUpdate {next_update})"
+        )));
+    }
+
+    Ok(OcspResponse {
+        der,
+        this_update,
+        next_update,
+    })
+}
+
+/// The parts of a BasicOCSPResponse needed to validate it
+struct BasicResponse<'a> {
+    /// Full encoding of tbsResponseData, over which the signature is computed
+    tbs_raw: &'a [u8],
+    responses: Vec<SingleResponse<'a>>,
+    /// Full encoding of the signature AlgorithmIdentifier
+    signature_algorithm: &'a [u8],
+    signature: BitString<'a>,
+    /// Content of the `certs` SEQUENCE, if present
+    certs: Option<&'a [u8]>,
+}
+
+struct SingleResponse<'a> {
+    /// Full encoding of the CertID
+    cert_id: &'a [u8],
+    status: CertStatus,
+    this_update: GeneralizedTime,
+    next_update: Option<GeneralizedTime>,
+}
+
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+struct ResponseBytes<'a> {
+    response_type: Oid<'a>,
+    response: &'a [u8],
+}
+
+/// Parse an OCSPResponse into its status and, if present, its response bytes
+fn parse_response(i: &[u8]) -> ParseResult<'_, (Enumerated, Option<ResponseBytes<'_>>)> {
+    // OCSPResponse ::= SEQUENCE { responseStatus, responseBytes [0] EXPLICIT OPTIONAL }
+    Sequence::from_der_and_then(i, |i| {
+        let (i, status) = Enumerated::from_der(i)?;
+        // ResponseBytes ::= SEQUENCE { responseType OID, response OCTET STRING }
+        let (i, response_bytes) = OptTaggedParser::from(0).parse_der(i, |_, i| {
+            Sequence::from_der_and_then(i, |i| {
+                let (i, response_type) = Oid::from_der(i)?;
+                let (i, response) = <&[u8]>::from_der(i)?;
+                Ok((
+                    i,
+                    ResponseBytes {
+                        response_type,
+                        response,
+                    },
+                ))
+            })
+        })?;
+        Ok((i, (status, response_bytes)))
+    })
+}
+
+fn parse_basic_response(i: &[u8]) -> ParseResult<'_, BasicResponse<'_>> {
+    // BasicOCSPResponse ::= SEQUENCE { tbsResponseData, signatureAlgorithm, signature, certs }
+    Sequence::from_der_and_then(i, |i| {
+        let (i, (tbs_raw, responses)) = consumed(parse_response_data)(i)?;
+        let (i, (signature_algorithm, _)) = consumed(Any::from_der)(i)?;
+        let (i, signature) = BitString::from_der(i)?;
+        let (i, certs) = OptTaggedParser::from(0).parse_der(i, |_, i| sequence_content(i))?;
+        Ok((
+            i,
+            BasicResponse {
+                tbs_raw,
+                responses,
+                signature_algorithm,
+                signature,
+                certs,
+            },
+        ))
+    })
+}
+
+fn parse_response_data(i: &[u8]) -> ParseResult<'_, Vec<SingleResponse<'_>>> {
+    // ResponseData ::= SEQUENCE { version [0], responderID, producedAt, responses, ... }
+    Sequence::from_der_and_then(i, |i| {
+        let (i, _version) = OptTaggedParser::from(0).parse_der(i, |_, i| u32::from_der(i))?;
+        let (i, _responder_id) = Any::from_der(i)?;
+        let (i, _produced_at) = GeneralizedTime::from_der(i)?;
+        Sequence::from_der_and_then(i, all_consuming(many0(complete(parse_single_response))))
+    })
+}
+
+fn parse_single_response(i: &[u8]) -> ParseResult<'_, SingleResponse<'_>> {
+    // SingleResponse ::= SEQUENCE { certID, certStatus, thisUpdate, nextUpdate [0], ... }
+    Sequence::from_der_and_then(i, |i| {
+        let (i, (cert_id, _)) = consumed(Any::from_der)(i)?;
+        // CertStatus ::= CHOICE { good [0], revoked [1], unknown [2] }, all IMPLICIT
+        let (i, status) = Any::from_der(i)?;
+        let status = match (status.class(), status.tag()) {
+            (Class::ContextSpecific, Tag(0)) => CertStatus::Good,
+            (Class::ContextSpecific, Tag(1)) => CertStatus::Revoked,
+            _ => CertStatus::Unknown,
+        };
+        let (i, this_update) = GeneralizedTime::from_der(i)?;
+        let (i, next_update) =
+            OptTaggedParser::from(0).parse_der(i, |_, i| GeneralizedTime::from_der(i))?;
+        Ok((
+            i,
+            SingleResponse {
+                cert_id,
+                status,
+                this_update,
+                next_update,
+            },
+        ))
+    })
+}
+
+/// Parse a SEQUENCE, returning its content
+fn sequence_content(i: &[u8]) -> ParseResult<'_, &[u8]> {
+    Sequence::from_der_and_then(i, |content| Ok((&[], content)))
+}
+
+fn malformed(e: x509_parser::nom::Err<asn1_rs::Error>) -> Error {
+    Error::Ocsp(format!("Malformed OCSP response: {e}"))
+}
+
+/// Verify the response signature by the issuer or by a delegated OCSP signing certificate
+fn verify_signer(
+    issuer: &X509Certificate<'_>,
+    certs: Option<&[u8]>,
+    signature_algorithm: &AlgorithmIdentifier<'_>,
+    signature: &BitString<'_>,
+    tbs_raw: &[u8],
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let verify = |public_key: &SubjectPublicKeyInfo<'_>| {
+        x509_parser::verify::verify_signature(public_key, signature_algorithm, signature, tbs_raw)
+            .is_ok()
+    };
+
+    if verify(issuer.public_key()) {
+        return Ok(());
+    }
+
+    let mut certs = certs.unwrap_or_default();
+    while let Ok((rest, responder)) = X509Certificate::from_der(certs) {
+        certs = rest;
+        let delegated = responder
+            .extended_key_usage()
+            .ok()
+            .flatten()
+            .is_some_and(|eku| eku.value.ocsp_signing);
+        let current = responder
+            .validity()
+            .is_valid_at(ASN1Time::from_timestamp(now.timestamp()).unwrap_or(ASN1Time::now()));
+
+        if delegated
+            && current
+            && responder
+                .verify_signature(Some(issuer.public_key()))
+                .is_ok()
+            && verify(responder.public_key())
+        {
+            return Ok(());
+        }
+    }
+
+    Err(Error::Ocsp(
+        "OCSP response is not signed by the issuer or an authorized responder".to_string(),
+    ))
+}
+
+fn to_datetime(time: &GeneralizedTime) -> Result<DateTime<Utc>> {
+    time.utc_datetime()
+        .ok()
+        .and_then(|time| DateTime::from_timestamp(time.unix_timestamp(), 0))
+        .ok_or_else(|| Error::Ocsp("Invalid GeneralizedTime in OCSP response".to_string()))
+}
+
+/// DER-encode a value; writing into a `Vec` cannot fail
+fn to_der(value: impl ToDer) -> Vec<u8> {
+    value
+        .to_der_vec()
+        .expect("DER encoding into a Vec cannot fail")
+}
+
+/// DER-encode a SEQUENCE of already encoded elements
+fn der_sequence(items: &[&[u8]]) -> Vec<u8> {
+    to_der(Sequence::new(items.concat().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use x509_parser::der_parser::asn1_rs::{Header, Length};
+    use x509_parser::oid_registry::OID_SIG_ECDSA_WITH_SHA256;
+
+    struct TestPki {
+        chain_pem: String,
+        ca_key: KeyPair,
+    }
+
+    fn test_pki() -> TestPki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf_params = CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        let leaf_cert = leaf_params.signed_by(&leaf_key, &ca_cert, &ca_key).unwrap();
+
+        TestPki {
+            chain_pem: format!("{}{}", leaf_cert.pem(), ca_cert.pem()),
+            ca_key,
+        }
+    }
+
+    /// DER-encode a context-specific element with the given content
+    fn context(tag: u32, constructed: bool, content: &[u8]) -> Vec<u8> {
+        let length = Length::Definite(content.len());
+        let header = Header::new(Class::ContextSpecific, constructed, Tag(tag), length);
+        to_der(Any::new(header, content))
+    }
+
+    fn time(value: &str) -> Vec<u8> {
+        to_der(GeneralizedTime::from_bytes(value.as_bytes()).unwrap())
+    }
+
+    /// Build an OCSPResponse for the chain's leaf, signed by the CA
+    fn signed_response(pki: &TestPki, status: &[u8], next_update: &str) -> Vec<u8> {
+        let chain = parse_chain(&pki.chain_pem).unwrap();
+        let (leaf, issuer) = chain.certificates().unwrap();
+
+        let single = der_sequence(&[
+            &cert_id(&leaf, &issuer),
+            status,
+            &time("20240101000000Z"),
+            &context(0, true, &time(next_update)),
+        ]);
+        let tbs = der_sequence(&[
+            &context(2, true, &to_der(OctetString::new(&[0; 20]))),
+            &time("20240101000000Z"),
+            &der_sequence(&[&single]),
+        ]);
+
+        let signer = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_ASN1_SIGNING,
+            &pki.ca_key.serialize_der(),
+            &SystemRandom::new(),
+        )
+        .unwrap();
+        let signature = signer.sign(&SystemRandom::new(), &tbs).unwrap();
+
+        let basic = der_sequence(&[
+            &tbs,
+            &der_sequence(&[&to_der(OID_SIG_ECDSA_WITH_SHA256)]),
+            &to_der(BitString::new(0, signature.as_ref())),
+        ]);
+        let response_bytes = der_sequence(&[
+            &to_der(OID_PKIX_OCSP_BASIC),
+            &to_der(OctetString::new(&basic)),
+        ]);
+        der_sequence(&[
+            &to_der(Enumerated::new(0)),
+            &context(0, true, &response_bytes),
+        ])
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-02T00:00:00Z")
+            .unwrap()
+            .to_utc()
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_local_responder() {
+        let pki = test_pki();
+        let response = signed_response(&pki, &context(0, false, &[]), "20240108000000Z");
+
+        // Minimal HTTP/1.1 stand-in for an OCSP responder
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let expected_request = {
+            let chain = parse_chain(&pki.chain_pem).unwrap();
+            let (leaf, issuer) = chain.certificates().unwrap();
+            build_request(&leaf, &issuer)
+        };
+        let responder_body = response.clone();
+        let responder = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 4096];
+            while !received.ends_with(&expected_request) {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed before the full request arrived");
+                received.extend_from_slice(&buf[..n]);
+            }
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/ocsp-response\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                responder_body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&responder_body).await.unwrap();
+        });
+
+        let chain = parse_chain(&pki.chain_pem).unwrap();
+        let (leaf, issuer) = chain.certificates().unwrap();
+        let der = post(&url, build_request(&leaf, &issuer)).await.unwrap();
+        responder.await.unwrap();
+
+        let validated = validate(der, &leaf, &issuer, now()).unwrap();
+        assert_eq!(validated.der, response);
+        assert!(!validated.needs_refresh(now()));
+        assert!(validated.needs_refresh(now() + chrono::Duration::days(3)));
+    }
+
+    #[test]
+    fn test_rejects_revoked_and_expired_responses() {
+        let pki = test_pki();
+
+        let revoked = signed_response(
+            &pki,
+            &context(1, true, &time("20231231000000Z")),
+            "20240108000000Z",
+        );
+        assert!(validate_stored(revoked, &pki.chain_pem, now()).is_err());
+
+        let expired = signed_response(&pki, &context(0, false, &[]), "20240101120000Z");
+        assert!(validate_stored(expired, &pki.chain_pem, now()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_response_signed_by_another_key() {
+        let pki = test_pki();
+        let other = test_pki();
+        let response = signed_response(
+            &TestPki {
+                chain_pem: pki.chain_pem.clone(),
+                ca_key: other.ca_key,
+            },
+            &context(0, false, &[]),
+            "20240108000000Z",
+        );
+        assert!(validate_stored(response, &pki.chain_pem, now()).is_err());
+    }
+}
@@ -1,49 +1,342 @@
+use std::net::IpAddr;
 use std::time::Duration;
 
+use chrono::Utc;
+
 use instant_acme::{
     Account, AuthorizationStatus, Challenge, ChallengeType, Identifier, NewOrder, Order,
     OrderStatus, Problem,
 };
-use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_ECDSA_P256_SHA256};
+use rcgen::{
+    CertificateParams, CustomExtension, DistinguishedName, DnType, KeyPair, PKCS_ECDSA_P256_SHA256,
+    PKCS_ECDSA_P384_SHA384, SanType, SignatureAlgorithm,
+};
 use tracing::{debug, error, info, warn};
 
+use crate::config::{CsrOptions, KeyType};
 use crate::error::{Error, Result};
 
-use super::challenge::{ActiveChallenge, ChallengeState};
+use super::account::IssuerAccounts;
+use super::challenge::{ActiveChallenge, ChallengePublisher, ChallengeState};
+use super::storage::{CertificateStorage, OrderKind, PendingOrder};
+
+/// OID of the TLS Feature extension (RFC 7633)
+const TLS_FEATURE_OID: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 24];
+
+/// TLS Feature extension value requesting status_request (Must-Staple): SEQUENCE { INTEGER 5 }
+const MUST_STAPLE_FEATURES: &[u8] = &[0x30, 0x03, 0x02, 0x01, 0x05];
+
+/// Identifiers, key and CSR options of a certificate to order
+pub struct CertificateRequest<'a> {
+    /// Name of the issuer whose account places the order
+    pub issuer: &'a str,
+    /// Whether this is the certificate's production or preflight order
+    pub kind: OrderKind,
+    pub domains: &'a [String],
+    pub ip_addresses: &'a [IpAddr],
+    pub key_pair: &'a KeyPair,
+    pub csr: &'a CsrOptions,
+}
 
 /// Handles certificate ordering workflow
 pub struct CertificateOrder;
 
 impl CertificateOrder {
-    /// Order a new certificate for the given domains
+    /// Order a certificate for the requested domains and IP addresses
     ///
-    /// Returns the certificate chain PEM and private key PEM
+    /// The order is recorded in storage while in progress, and an order recorded before a
+    /// restart is resumed if the CA still considers it in progress.
+    ///
+    /// Returns the certificate chain PEM and the private key PEM. The key is the requested
+    /// one, unless a resumed order had already been finalized with another key.
     pub async fn order(
         account: &Account,
+        accounts: &IssuerAccounts,
+        storage: &CertificateStorage,
         cert_name: &str,
-        domains: &[String],
+        request: &CertificateRequest<'_>,
+        challenge_state: &ChallengeState,
+        publisher: &ChallengePublisher,
+    ) -> Result<(String, String)> {
+        info!(
+            cert_name,
+            domains = ?request.domains,
+            ip_addresses = ?request.ip_addresses,
+            "Starting certificate order"
+        );
+
+        let identifiers: Vec<Identifier> = request
+            .domains
+            .iter()
+            .map(|d| Identifier::Dns(d.clone()))
+            .chain(request.ip_addresses.iter().map(|ip| Identifier::Ip(*ip)))
+            .collect();
+
+        let (mut order, mut pending) =
+            match Self::resume(account, accounts, storage, cert_name, request, &identifiers).await?
+            {
+                Some(resumed) => resumed,
+                None => {
+                    let order = account.new_order(&NewOrder::new(&identifiers)).await?;
+                    let pending = PendingOrder {
+                        url: order.url().to_string(),
+                        issuer: request.issuer.to_string(),
+                        identifiers,
+                        created: Utc::now(),
+                        challenges: Vec::new(),
+                        private_key_pem: None,
+                    };
+                    storage
+                        .save_pending_order(cert_name, request.kind, &pending)
+                        .await?;
+                    (order, pending)
+                }
+            };
+
+        let result = Self::complete(
+            &mut order,
+            &mut pending,
+            storage,
+            cert_name,
+            request,
+            challenge_state,
+            publisher,
+        )
+        .await;
+
+        // Leave no authorizations pending on the CA, where later orders would reuse them
+        if result.is_err() {
+            Self::deactivate_authorizations(&mut order, cert_name).await;
+        }
+
+        // Only an order interrupted by a restart is resumed
+        if let Err(e) = storage.remove_pending_order(cert_name, request.kind).await {
+            warn!(cert_name, error = %e, "Failed to remove pending order record");
+        }
+
+        result
+    }
+
+    /// Load the order recorded for a certificate before a restart, if it can be continued
+    ///
+    /// Orders from another issuer or for other identifiers, and orders the CA no longer
+    /// considers in progress, are abandoned and their pending authorizations deactivated.
+    async fn resume(
+        account: &Account,
+        accounts: &IssuerAccounts,
+        storage: &CertificateStorage,
+        cert_name: &str,
+        request: &CertificateRequest<'_>,
+        identifiers: &[Identifier],
+    ) -> Result<Option<(Order, PendingOrder)>> {
+        let Some(pending) = storage.load_pending_order(cert_name, request.kind).await? else {
+            return Ok(None);
+        };
+
+        if pending.issuer != request.issuer {
+            info!(
+                cert_name,
+                url = pending.url,
+                issuer = pending.issuer,
+                "Abandoning pending order from a different issuer"
+            );
+            Self::deactivate_foreign_authorizations(accounts, &pending, cert_name).await;
+            storage
+                .remove_pending_order(cert_name, request.kind)
+                .await?;
+            return Ok(None);
+        }
+
+        let mut order = match account.order(pending.url.clone()).await {
+            Ok(order) => order,
+            Err(e) => {
+                warn!(
+                    cert_name,
+                    url = pending.url,
+                    error = %e,
+                    "Failed to load pending order, starting a new one"
+                );
+                storage
+                    .remove_pending_order(cert_name, request.kind)
+                    .await?;
+                return Ok(None);
+            }
+        };
+
+        let status = order.state().status;
+        match resumption(&pending, identifiers, status) {
+            Resumption::Resume => {
+                info!(
+                    cert_name,
+                    url = pending.url,
+                    ?status,
+                    "Resuming pending order"
+                );
+                Ok(Some((order, pending)))
+            }
+            Resumption::Abandon(reason) => {
+                info!(
+                    cert_name,
+                    url = pending.url,
+                    ?status,
+                    reason,
+                    "Abandoning stale pending order"
+                );
+                Self::deactivate_authorizations(&mut order, cert_name).await;
+                storage
+                    .remove_pending_order(cert_name, request.kind)
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Deactivate the pending authorizations of an order placed with another issuer's account
+    ///
+    /// Only that account can act on the order, so nothing is done if its issuer is no
+    /// longer configured.
+    async fn deactivate_foreign_authorizations(
+        accounts: &IssuerAccounts,
+        pending: &PendingOrder,
+        cert_name: &str,
+    ) {
+        let Some(issuer) = accounts.find(&pending.issuer) else {
+            warn!(
+                cert_name,
+                issuer = pending.issuer,
+                "Issuer of the pending order is no longer configured, leaving its authorizations to expire"
+            );
+            return;
+        };
+
+        let account = issuer.account.read().await;
+        match account.order(pending.url.clone()).await {
+            Ok(mut order) => Self::deactivate_authorizations(&mut order, cert_name).await,
+            Err(e) => warn!(
+                cert_name,
+                url = pending.url,
+                error = %e,
+                "Failed to load pending order to deactivate its authorizations"
+            ),
+        }
+    }
+
+    /// Drive an order from its current status to an issued certificate
+    async fn complete(
+        order: &mut Order,
+        pending: &mut PendingOrder,
+        storage: &CertificateStorage,
+        cert_name: &str,
+        request: &CertificateRequest<'_>,
         challenge_state: &ChallengeState,
-        on_challenges_ready: impl Fn() + Send,
-    ) -> Result<(String, String, KeyPair)> {
-        info!(cert_name, ?domains, "Starting certificate order");
-
-        // Create order
-        let identifiers: Vec<Identifier> =
-            domains.iter().map(|d| Identifier::Dns(d.clone())).collect();
-
-        let mut order = account
-            .new_order(&NewOrder {
-                identifiers: &identifiers,
-            })
+        publisher: &ChallengePublisher,
+    ) -> Result<(String, String)> {
+        let status = order.state().status;
+        if status == OrderStatus::Pending {
+            Self::validate(
+                order,
+                pending,
+                storage,
+                cert_name,
+                request,
+                challenge_state,
+                publisher,
+            )
             .await?;
+        }
+
+        let private_key_pem = match (status, &pending.private_key_pem) {
+            (OrderStatus::Processing | OrderStatus::Valid, Some(private_key_pem)) => {
+                private_key_pem.clone()
+            }
+            _ => {
+                // Record the key first, so a restart during finalization can still use the result
+                let private_key_pem = request.key_pair.serialize_pem();
+                pending.private_key_pem = Some(private_key_pem.clone());
+                storage
+                    .save_pending_order(cert_name, request.kind, pending)
+                    .await?;
+
+                let csr_der = Self::generate_csr(request)?;
+                order.finalize_csr(&csr_der).await?;
+                private_key_pem
+            }
+        };
+
+        // Wait for certificate
+        Self::wait_for_order_ready(order, cert_name, request.domains).await?;
+
+        // Get certificate
+        let cert_chain_pem = order
+            .certificate()
+            .await?
+            .ok_or_else(|| Error::ChallengeFailed("No certificate returned".to_string()))?;
+
+        info!(cert_name, "Certificate issued successfully");
+
+        Ok((cert_chain_pem, private_key_pem))
+    }
+
+    /// Complete the order's pending authorizations over HTTP-01
+    async fn validate(
+        order: &mut Order,
+        pending: &mut PendingOrder,
+        storage: &CertificateStorage,
+        cert_name: &str,
+        request: &CertificateRequest<'_>,
+        challenge_state: &ChallengeState,
+        publisher: &ChallengePublisher,
+    ) -> Result<()> {
+        // Republish challenges recorded before a restart, as the CA may be validating them
+        for challenge in &pending.challenges {
+            challenge_state.add(challenge.clone()).await;
+        }
+
+        let result = async {
+            let challenges = Self::collect_challenges(order, cert_name).await?;
+            if challenges.is_empty() {
+                return Ok(());
+            }
+
+            for challenge in &challenges {
+                challenge_state.add(challenge.clone()).await;
+            }
+            pending.challenges = challenges.clone();
+            storage
+                .save_pending_order(cert_name, request.kind, pending)
+                .await?;
+
+            // Publish the challenges via xDS, then signal them ready once Envoy serves them
+            let generation = challenge_state.generation().await;
+            publisher.notify();
+            publisher.wait_until_served(generation, &challenges).await?;
+            Self::set_challenges_ready(order).await?;
+            Self::wait_for_order_ready(order, cert_name, request.domains).await
+        }
+        .await;
+
+        // Clean up challenges even on failure
+        challenge_state.clear_for_cert(cert_name).await;
+        publisher.notify();
 
-        // Process authorizations
-        let authorizations = order.authorizations().await?;
-        let mut challenges_to_complete = Vec::new();
+        result
+    }
+
+    /// Build the HTTP-01 challenges for the order's pending authorizations
+    ///
+    /// Fails if any authorization can no longer become valid.
+    async fn collect_challenges(
+        order: &mut Order,
+        cert_name: &str,
+    ) -> Result<Vec<ActiveChallenge>> {
+        let mut authorizations = order.authorizations();
+        let mut challenges = Vec::new();
 
-        for authz in &authorizations {
+        while let Some(authz) = authorizations.next().await {
+            let mut authz = authz?;
+            let identifier = authz.identifier().to_string();
             debug!(
-                identifier = ?authz.identifier,
+                identifier,
                 status = ?authz.status,
                 "Processing authorization"
             );
@@ -51,90 +344,104 @@ impl CertificateOrder {
             match authz.status {
                 AuthorizationStatus::Pending => {
                     // Find HTTP-01 challenge
-                    let challenge = authz
-                        .challenges
-                        .iter()
-                        .find(|c| c.r#type == ChallengeType::Http01)
-                        .ok_or_else(|| {
-                            Error::ChallengeFailed("No HTTP-01 challenge available".to_string())
-                        })?;
-
-                    // Get key authorization
-                    let key_auth = order.key_authorization(challenge);
-
-                    // Add to challenge state
-                    let active_challenge = ActiveChallenge {
+                    let challenge = authz.challenge(ChallengeType::Http01).ok_or_else(|| {
+                        Error::ChallengeFailed("No HTTP-01 challenge available".to_string())
+                    })?;
+
+                    challenges.push(ActiveChallenge {
                         token: challenge.token.clone(),
-                        key_authorization: key_auth.as_str().to_string(),
+                        key_authorization: challenge.key_authorization().as_str().to_string(),
                         cert_name: cert_name.to_string(),
-                    };
-
-                    challenge_state.add(active_challenge).await;
-                    challenges_to_complete.push(challenge.url.clone());
+                        identifier,
+                    });
                 }
                 AuthorizationStatus::Valid => {
                     debug!("Authorization already valid");
                 }
                 AuthorizationStatus::Invalid
                 | AuthorizationStatus::Revoked
-                | AuthorizationStatus::Expired => {
+                | AuthorizationStatus::Expired
+                | AuthorizationStatus::Deactivated => {
                     let summary = Self::summarize_challenge_errors(&authz.challenges);
-                    Self::log_challenge_errors(cert_name, &authz.identifier, &authz.challenges);
-                    challenge_state.clear_for_cert(cert_name).await;
-                    return Err(Error::ChallengeFailed(match summary {
+                    Self::log_challenge_errors(cert_name, &identifier, &authz.challenges);
+                    let message = match summary {
                         Some(summary) => format!(
-                            "Authorization {:?} for {:?} failed: {}",
-                            authz.status, authz.identifier, summary
+                            "Authorization {:?} for {} failed: {}",
+                            authz.status, identifier, summary
                         ),
                         None => format!(
-                            "Authorization {:?} for {:?} failed without problem details",
-                            authz.status, authz.identifier
+                            "Authorization {:?} for {} failed without problem details",
+                            authz.status, identifier
                         ),
-                    }));
+                    };
+                    return Err(
+                        match authz.challenges.iter().find_map(|c| c.error.clone()) {
+                            Some(problem) => Error::ChallengeProblem { message, problem },
+                            None => Error::ChallengeFailed(message),
+                        },
+                    );
                 }
             }
         }
 
-        // Notify that challenges are ready (triggers xDS update)
-        let challenge_result = if !challenges_to_complete.is_empty() {
-            on_challenges_ready();
-
-            // Small delay to allow xDS to propagate
-            tokio::time::sleep(Duration::from_secs(2)).await;
+        Ok(challenges)
+    }
 
-            // Signal challenges ready to ACME server
-            for url in &challenges_to_complete {
-                order.set_challenge_ready(url).await?;
+    /// Deactivate the order's authorizations that are still pending
+    ///
+    /// CAs reuse pending authorizations for later orders, so a failure would otherwise stick
+    /// until they expire.
+    async fn deactivate_authorizations(order: &mut Order, cert_name: &str) {
+        let mut authorizations = order.authorizations();
+        while let Some(authz) = authorizations.next().await {
+            let mut authz = match authz {
+                Ok(authz) => authz,
+                Err(e) => {
+                    warn!(cert_name, error = %e, "Failed to load authorization");
+                    continue;
+                }
+            };
+            if authz.status != AuthorizationStatus::Pending {
+                continue;
+            }
+            // Cached states may predate validation, so confirm the authorization is still pending
+            match authz.refresh().await {
+                Ok(state) if state.status != AuthorizationStatus::Pending => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(cert_name, error = %e, "Failed to refresh authorization");
+                    continue;
+                }
             }
 
-            // Wait for challenges to complete
-            Self::wait_for_order_ready(&mut order, cert_name, domains).await
-        } else {
-            Ok(())
-        };
-
-        // Clean up challenges even on failure
-        challenge_state.clear_for_cert(cert_name).await;
-        challenge_result?;
-
-        // Generate CSR
-        let (csr_der, key_pair) = Self::generate_csr(domains)?;
-
-        // Finalize order
-        order.finalize(&csr_der).await?;
-
-        // Wait for certificate
-        Self::wait_for_order_ready(&mut order, cert_name, domains).await?;
-
-        // Get certificate
-        let cert_chain_pem = order
-            .certificate()
-            .await?
-            .ok_or_else(|| Error::ChallengeFailed("No certificate returned".to_string()))?;
+            let identifier = authz.identifier().to_string();
+            match authz.deactivate().await {
+                Ok(_) => info!(cert_name, identifier, "Deactivated pending authorization"),
+                Err(e) => warn!(
+                    cert_name,
+                    identifier,
+                    error = %e,
+                    "Failed to deactivate authorization"
+                ),
+            }
+        }
+    }
 
-        info!(cert_name, "Certificate issued successfully");
+    /// Signal every pending HTTP-01 challenge in the order as ready for validation
+    async fn set_challenges_ready(order: &mut Order) -> Result<()> {
+        // Authorization states are cached from the initial pass, so this makes no extra fetches
+        let mut authorizations = order.authorizations();
+        while let Some(authz) = authorizations.next().await {
+            let mut authz = authz?;
+            if authz.status != AuthorizationStatus::Pending {
+                continue;
+            }
+            if let Some(mut challenge) = authz.challenge(ChallengeType::Http01) {
+                challenge.set_ready().await?;
+            }
+        }
 
-        Ok((cert_chain_pem, key_pair.serialize_pem(), key_pair))
+        Ok(())
     }
 
     /// Wait for order to reach ready/valid state
@@ -171,11 +478,18 @@ impl CertificateOrder {
                             "Failed to load authorizations for invalid order"
                         );
                     }
-                    let summary = error.as_ref().map(Self::format_problem);
-                    return Err(Error::ChallengeFailed(match summary {
-                        Some(summary) => format!("Order became invalid: {summary}"),
-                        None => "Order became invalid without problem details".to_string(),
-                    }));
+                    return Err(match error {
+                        Some(problem) => Error::ChallengeProblem {
+                            message: format!(
+                                "Order became invalid: {}",
+                                Self::format_problem(&problem)
+                            ),
+                            problem,
+                        },
+                        None => Error::ChallengeFailed(
+                            "Order became invalid without problem details".to_string(),
+                        ),
+                    });
                 }
                 OrderStatus::Pending | OrderStatus::Processing => {
                     tokio::time::sleep(delay).await;
@@ -187,25 +501,29 @@ impl CertificateOrder {
         }
 
         Self::log_timeout_problem(cert_name, domains, last_status, last_error.as_ref());
-        Err(Error::ChallengeFailed(match last_status {
-            Some(status) => match last_error.as_ref() {
-                Some(problem) => format!(
-                    "Order did not complete in time (status={:?}): {}",
-                    status,
-                    Self::format_problem(problem)
-                ),
-                None => format!("Order did not complete in time (status={:?})", status),
-            },
+        let message = match last_status {
+            Some(status) => format!("Order did not complete in time (status={:?})", status),
             None => "Order did not complete in time".to_string(),
-        }))
+        };
+        Err(match last_error {
+            Some(problem) => Error::ChallengeProblem {
+                message: format!("{message}: {}", Self::format_problem(&problem)),
+                problem,
+            },
+            None => Error::ChallengeFailed(message),
+        })
     }
 
     async fn log_authorization_problems(order: &mut Order, cert_name: &str) -> Result<()> {
-        let authorizations = order.authorizations().await?;
+        let mut authorizations = order.authorizations();
         let mut logged = false;
 
-        for authz in &authorizations {
-            if Self::log_challenge_errors(cert_name, &authz.identifier, &authz.challenges) {
+        while let Some(authz) = authorizations.next().await {
+            let mut authz = authz?;
+            // Cached states predate validation, so fetch the current state first
+            let state = authz.refresh().await?;
+            let identifier = state.identifier().to_string();
+            if Self::log_challenge_errors(cert_name, &identifier, &state.challenges) {
                 logged = true;
             }
         }
@@ -258,11 +576,7 @@ impl CertificateOrder {
         }
     }
 
-    fn log_challenge_errors(
-        cert_name: &str,
-        identifier: &Identifier,
-        challenges: &[Challenge],
-    ) -> bool {
+    fn log_challenge_errors(cert_name: &str, identifier: &str, challenges: &[Challenge]) -> bool {
         let mut logged = false;
 
         for challenge in challenges {
@@ -270,7 +584,7 @@ impl CertificateOrder {
                 logged = true;
                 error!(
                     cert_name,
-                    identifier = ?identifier,
+                    identifier,
                     challenge_type = ?challenge.r#type,
                     challenge_status = ?challenge.status,
                     problem_detail = ?problem.detail,
@@ -314,17 +628,141 @@ impl CertificateOrder {
         parts.join(", ")
     }
 
-    /// Generate a CSR for the given domains
-    fn generate_csr(domains: &[String]) -> Result<(Vec<u8>, KeyPair)> {
-        let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)?;
+    /// Generate a CSR for the requested domains, IP addresses and options
+    ///
+    /// The common name defaults to the first domain; certificates for IP addresses only
+    /// have none unless one is chosen explicitly.
+    fn generate_csr(request: &CertificateRequest<'_>) -> Result<Vec<u8>> {
+        let csr = request.csr;
 
         let mut distinguished_name = DistinguishedName::new();
-        distinguished_name.push(DnType::CommonName, domains[0].clone());
+        let common_name = csr.common_name.as_ref().or(request.domains.first());
+        if let Some(common_name) = common_name.filter(|_| !csr.omit_common_name) {
+            distinguished_name.push(DnType::CommonName, common_name.clone());
+        }
+        let subject = &csr.subject;
+        for (dn_type, value) in [
+            (DnType::OrganizationName, &subject.organization),
+            (DnType::OrganizationalUnitName, &subject.organizational_unit),
+            (DnType::CountryName, &subject.country),
+            (DnType::StateOrProvinceName, &subject.state),
+            (DnType::LocalityName, &subject.locality),
+        ] {
+            if let Some(value) = value {
+                distinguished_name.push(dn_type, value.clone());
+            }
+        }
 
-        let mut params = CertificateParams::new(domains.to_vec())?;
+        let mut params = CertificateParams::new(request.domains.to_vec())?;
+        params.subject_alt_names.extend(
+            request
+                .ip_addresses
+                .iter()
+                .map(|ip| SanType::IpAddress(*ip)),
+        );
         params.distinguished_name = distinguished_name;
+        if csr.must_staple {
+            params
+                .custom_extensions
+                .push(CustomExtension::from_oid_content(
+                    TLS_FEATURE_OID,
+                    MUST_STAPLE_FEATURES.to_vec(),
+                ));
+        }
+
+        let csr = params.serialize_request(request.key_pair)?;
+        Ok(csr.der().to_vec())
+    }
+}
 
-        let csr = params.serialize_request(&key_pair)?;
-        Ok((csr.der().to_vec(), key_pair))
+/// Signature algorithm of keys of the given type
+pub fn key_algorithm(key_type: KeyType) -> &'static SignatureAlgorithm {
+    match key_type {
+        KeyType::EcdsaP256 => &PKCS_ECDSA_P256_SHA256,
+        KeyType::EcdsaP384 => &PKCS_ECDSA_P384_SHA384,
+    }
+}
+
+/// Whether an order recorded before a restart can be continued
+#[derive(Debug, PartialEq, Eq)]
+enum Resumption {
+    Resume,
+    /// Abandon the order, for the given reason
+    Abandon(&'static str),
+}
+
+/// Decide whether a recorded order can be continued, given its status on the CA
+fn resumption(
+    pending: &PendingOrder,
+    identifiers: &[Identifier],
+    status: OrderStatus,
+) -> Resumption {
+    if pending.identifiers != identifiers {
+        return Resumption::Abandon("identifiers changed");
+    }
+    match status {
+        OrderStatus::Pending | OrderStatus::Ready => Resumption::Resume,
+        // Without the key the CSR was signed with, the certificate is of no use
+        OrderStatus::Processing | OrderStatus::Valid if pending.private_key_pem.is_none() => {
+            Resumption::Abandon("private key not recorded")
+        }
+        OrderStatus::Processing | OrderStatus::Valid => Resumption::Resume,
+        OrderStatus::Invalid => Resumption::Abandon("order invalid"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resumption() {
+        let identifiers = vec![Identifier::Dns("example.com".to_string())];
+        let mut pending = PendingOrder {
+            url: "https://acme.test/order/1".to_string(),
+            issuer: "default".to_string(),
+            identifiers: identifiers.clone(),
+            created: Utc::now(),
+            challenges: Vec::new(),
+            private_key_pem: None,
+        };
+
+        assert_eq!(
+            resumption(&pending, &identifiers, OrderStatus::Pending),
+            Resumption::Resume
+        );
+        assert_eq!(
+            resumption(&pending, &identifiers, OrderStatus::Ready),
+            Resumption::Resume
+        );
+        assert_eq!(
+            resumption(&pending, &identifiers, OrderStatus::Invalid),
+            Resumption::Abandon("order invalid")
+        );
+        for status in [OrderStatus::Processing, OrderStatus::Valid] {
+            assert_eq!(
+                resumption(&pending, &identifiers, status),
+                Resumption::Abandon("private key not recorded")
+            );
+        }
+
+        pending.private_key_pem = Some("key".to_string());
+        for status in [OrderStatus::Processing, OrderStatus::Valid] {
+            assert_eq!(
+                resumption(&pending, &identifiers, status),
+                Resumption::Resume
+            );
+        }
+
+        let other = vec![
+            Identifier::Dns("example.com".to_string()),
+            Identifier::Dns("www.example.com".to_string()),
+        ];
+        for status in [OrderStatus::Pending, OrderStatus::Ready, OrderStatus::Valid] {
+            assert_eq!(
+                resumption(&pending, &other, status),
+                Resumption::Abandon("identifiers changed")
+            );
+        }
     }
 }
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::info;
+
+/// Token bucket limiting how often new ACME orders are created for an account
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket holding `burst` tokens, refilled at `per_hour` tokens per hour
+    pub fn new(burst: u32, per_hour: u32) -> Self {
+        Self {
+            capacity: f64::from(burst),
+            refill_per_sec: f64::from(per_hour) / 3600.0,
+            state: Mutex::new(BucketState {
+                tokens: f64::from(burst),
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until a token is available and take it
+    pub async fn acquire(&self, issuer: &str) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let now = Instant::now();
+                let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+                state.last_refill = now;
+
+                if state.tokens >= 1.0 {
+                    state.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - state.tokens) / self.refill_per_sec)
+            };
+
+            info!(
+                issuer,
+                wait_secs = wait.as_secs(),
+                "New order rate limit reached, waiting"
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
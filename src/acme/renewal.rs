@@ -1,49 +1,71 @@
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
-use chrono::Utc;
-use instant_acme::Account;
-use tokio::sync::RwLock;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use rcgen::{CertificateParams, DnType, KeyPair, SanType};
 use tracing::{debug, error, info, warn};
 
 use crate::config::CertificateConfig;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::xds::XdsState;
 
-use super::challenge::ChallengeState;
-use super::order::CertificateOrder;
-use super::storage::{CertificateStorage, StoredCert, parse_certificate_expiry};
+use super::account::{IssuerAccount, IssuerAccounts};
+use super::challenge::{ChallengePublisher, ChallengeState};
+use super::ip::detect_ip_addresses;
+use super::ocsp;
+use super::order::{CertificateOrder, CertificateRequest, key_algorithm};
+use super::storage::{
+    CertificateDetails, CertificateStorage, OrderKind, PreflightResult, StoredCert,
+    parse_certificate_details, parse_certificate_expiry,
+};
 
 /// Manages background certificate renewal
 pub struct RenewalManager {
     storage: Arc<CertificateStorage>,
-    account: Arc<RwLock<Account>>,
+    accounts: IssuerAccounts,
     challenge_state: ChallengeState,
     xds_state: Arc<XdsState>,
-    certificates: Vec<CertificateConfig>,
+    publisher: ChallengePublisher,
+    /// Managed certificates, replaced on configuration reload
+    certificates: RwLock<Vec<CertificateConfig>>,
+    /// Names of certificates with an issuance attempt underway
+    in_progress: Mutex<HashSet<String>>,
+    /// Held while issuing the certificates changed by a reload
+    reload_issuance: tokio::sync::Mutex<()>,
     renewal_threshold_days: i64,
+    max_concurrent_orders: usize,
 }
 
 impl RenewalManager {
     pub fn new(
         storage: Arc<CertificateStorage>,
-        account: Arc<RwLock<Account>>,
+        accounts: IssuerAccounts,
         challenge_state: ChallengeState,
         xds_state: Arc<XdsState>,
+        publisher: ChallengePublisher,
         certificates: Vec<CertificateConfig>,
+        max_concurrent_orders: usize,
     ) -> Self {
         Self {
             storage,
-            account,
+            accounts,
             challenge_state,
             xds_state,
-            certificates,
+            publisher,
+            certificates: RwLock::new(certificates),
+            in_progress: Mutex::new(HashSet::new()),
+            reload_issuance: tokio::sync::Mutex::new(()),
             renewal_threshold_days: 30,
+            max_concurrent_orders,
         }
     }
 
     /// Run the renewal check loop
-    pub async fn run(self, check_interval: Duration) {
+    pub async fn run(self: Arc<Self>, check_interval: Duration) {
         info!(
             ?check_interval,
             threshold_days = self.renewal_threshold_days,
@@ -59,47 +81,145 @@ impl RenewalManager {
         }
     }
 
+    /// Snapshot of the managed certificates
+    fn certificates(&self) -> Vec<CertificateConfig> {
+        self.certificates.read().unwrap().clone()
+    }
+
+    /// Whether a certificate is still managed
+    fn is_managed(&self, name: &str) -> bool {
+        self.certificates
+            .read()
+            .unwrap()
+            .iter()
+            .any(|c| c.name == name)
+    }
+
     /// Check all certificates and renew if needed
     pub async fn check_and_renew(&self) -> Result<()> {
         debug!("Checking certificates for renewal");
 
-        for cert_config in &self.certificates {
-            match self.check_certificate(&cert_config.name).await {
-                Ok(needs_renewal) => {
-                    if needs_renewal {
-                        info!(name = cert_config.name, "Certificate needs renewal");
-                        if let Err(e) = self.renew_certificate(cert_config).await {
-                            error!(
-                                name = cert_config.name,
-                                error = %e,
-                                "Failed to renew certificate"
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
+        let certificates = self.certificates();
+        self.for_each_order(&certificates, |cert_config| {
+            self.check_and_renew_one(cert_config)
+        })
+        .await;
+
+        self.refresh_ocsp_staples().await;
+
+        Ok(())
+    }
+
+    /// Refresh OCSP staples for all certificates with stapling enabled
+    async fn refresh_ocsp_staples(&self) {
+        let certificates = self.certificates();
+        futures::stream::iter(certificates.iter().filter(|c| c.ocsp_stapling))
+            .for_each_concurrent(self.max_concurrent_orders, |cert_config| async move {
+                if let Err(e) = self.refresh_ocsp_staple(&cert_config.name).await {
                     warn!(
                         name = cert_config.name,
                         error = %e,
-                        "Failed to check certificate"
+                        "Failed to refresh OCSP staple"
                     );
-                    // If we can't check, try to issue
-                    if let Err(e) = self.renew_certificate(cert_config).await {
+                }
+            })
+            .await;
+    }
+
+    /// Serve the stored OCSP response for a certificate, fetching a new one from the
+    /// responder when it is missing or past the halfway point of its validity
+    async fn refresh_ocsp_staple(&self, name: &str) -> Result<()> {
+        // The stored certificate, if any, is not what Envoy serves in place of a placeholder
+        if self.storage.load_status(name).await?.serving_placeholder {
+            debug!(name, "Serving a placeholder, skipping OCSP stapling");
+            return Ok(());
+        }
+
+        let Some(cert) = self.storage.load_certificate(name).await? else {
+            return Ok(());
+        };
+
+        let now = Utc::now();
+        let stored = match self.storage.load_ocsp_response(name).await? {
+            Some(der) => match ocsp::validate_stored(der, &cert.cert_chain_pem, now) {
+                Ok(response) => Some(response),
+                Err(e) => {
+                    debug!(name, error = %e, "Stored OCSP response is no longer valid");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if let Some(response) = &stored
+            && !response.needs_refresh(now)
+        {
+            self.xds_state
+                .update_ocsp_staple(name, Some(response.der.clone()))
+                .await;
+            return Ok(());
+        }
+
+        match ocsp::fetch(&cert.cert_chain_pem).await {
+            Ok(response) => {
+                self.storage.save_ocsp_response(name, &response.der).await?;
+                info!(
+                    name,
+                    this_update = %response.this_update,
+                    next_update = ?response.next_update,
+                    "OCSP staple refreshed"
+                );
+                self.xds_state
+                    .update_ocsp_staple(name, Some(response.der))
+                    .await;
+                Ok(())
+            }
+            Err(e) => {
+                // Keep stapling the previous response while it is still valid
+                self.xds_state
+                    .update_ocsp_staple(name, stored.map(|response| response.der))
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Check a single certificate and renew it if needed
+    async fn check_and_renew_one(&self, cert_config: &CertificateConfig) {
+        match self.check_certificate(cert_config).await {
+            Ok(needs_renewal) => {
+                if needs_renewal {
+                    info!(name = cert_config.name, "Certificate needs renewal");
+                    if let Err(e) = self.attempt_issuance(cert_config).await {
                         error!(
                             name = cert_config.name,
                             error = %e,
-                            "Failed to issue certificate"
+                            "Failed to renew certificate"
                         );
                     }
                 }
             }
+            Err(e) => {
+                warn!(
+                    name = cert_config.name,
+                    error = %e,
+                    "Failed to check certificate"
+                );
+                // If we can't check, try to issue
+                if let Err(e) = self.attempt_issuance(cert_config).await {
+                    error!(
+                        name = cert_config.name,
+                        error = %e,
+                        "Failed to issue certificate"
+                    );
+                }
+            }
         }
-
-        Ok(())
     }
 
-    /// Check if a certificate needs renewal
-    async fn check_certificate(&self, name: &str) -> Result<bool> {
+    /// Check if a certificate needs renewal or no longer matches its configuration
+    async fn check_certificate(&self, cert_config: &CertificateConfig) -> Result<bool> {
+        let name = cert_config.name.as_str();
         let cert = match self.storage.load_certificate(name).await? {
             Some(c) => c,
             None => {
@@ -118,23 +238,172 @@ impl RenewalManager {
             "Certificate expiry check"
         );
 
-        Ok(days_until_expiry < self.renewal_threshold_days)
+        if key_rotation_due(cert_config, &cert, now) {
+            info!(
+                name,
+                key_rotation_days = cert_config.key_rotation_days,
+                "Scheduled key rotation is due"
+            );
+            return Ok(true);
+        }
+
+        Ok(days_until_expiry < self.renewal_threshold_days
+            || !self.matches_config(cert_config, &cert))
+    }
+
+    /// Whether a stored certificate still matches its configuration, logging any differences
+    fn matches_config(&self, cert_config: &CertificateConfig, cert: &StoredCert) -> bool {
+        let changes = Self::ip_addresses(cert_config).and_then(|ip_addresses| {
+            let directory_url = self.accounts.get(cert_config.issuer_name())?.directory_url;
+            let details = parse_certificate_details(&cert.cert_chain_pem)?;
+            Ok(describe_changes(
+                cert_config,
+                &ip_addresses,
+                &directory_url,
+                cert,
+                &details,
+            ))
+        });
+
+        match changes {
+            Ok(changes) if changes.is_empty() => true,
+            Ok(changes) => {
+                info!(
+                    name = cert_config.name,
+                    changes = changes.join("; "),
+                    "Certificate no longer matches configuration, reissuing"
+                );
+                false
+            }
+            Err(e) => {
+                warn!(
+                    name = cert_config.name,
+                    error = %e,
+                    "Failed to compare certificate with configuration, reissuing"
+                );
+                false
+            }
+        }
+    }
+
+    /// Issue a certificate unless a previous failure's backoff is still in effect
+    ///
+    /// The outcome is persisted so the backoff also applies across restarts.
+    async fn attempt_issuance(&self, cert_config: &CertificateConfig) -> Result<()> {
+        self.exclusive(
+            &cert_config.name,
+            self.attempt_issuance_unguarded(cert_config),
+        )
+        .await
+    }
+
+    /// Run `issue` unless an issuance of the certificate is already underway
+    ///
+    /// A reload may ask for a certificate the renewal loop is already issuing.
+    async fn exclusive(&self, name: &str, issue: impl Future<Output = Result<()>>) -> Result<()> {
+        if !self.in_progress.lock().unwrap().insert(name.to_string()) {
+            debug!(name, "Issuance already in progress");
+            return Ok(());
+        }
+
+        let result = issue.await;
+        self.in_progress.lock().unwrap().remove(name);
+        result
+    }
+
+    /// Run `order` for each certificate, with at most `max_concurrent_orders` at once
+    async fn for_each_order<'a, Fut>(
+        &self,
+        certificates: impl IntoIterator<Item = &'a CertificateConfig>,
+        order: impl FnMut(&'a CertificateConfig) -> Fut,
+    ) where
+        Fut: Future<Output = ()>,
+    {
+        futures::stream::iter(certificates)
+            .for_each_concurrent(self.max_concurrent_orders, order)
+            .await;
+    }
+
+    async fn attempt_issuance_unguarded(&self, cert_config: &CertificateConfig) -> Result<()> {
+        let mut status = self.storage.load_status(&cert_config.name).await?;
+
+        if let Some(next_attempt) = status.backoff_until(Utc::now()) {
+            info!(
+                name = cert_config.name,
+                failed_attempts = status.failed_attempts,
+                %next_attempt,
+                last_error = status.last_error.as_deref().unwrap_or(""),
+                "Skipping issuance until backoff expires"
+            );
+            return Ok(());
+        }
+
+        let result = self.renew_certificate(cert_config).await;
+        match &result {
+            Ok(()) => {
+                if status.serving_placeholder {
+                    info!(
+                        name = cert_config.name,
+                        "Placeholder certificate replaced by issued certificate"
+                    );
+                }
+                status.record_success(Utc::now());
+            }
+            Err(e) => {
+                status.record_failure(e, Utc::now());
+                warn!(
+                    name = cert_config.name,
+                    failed_attempts = status.failed_attempts,
+                    problem_type = status.last_problem_type.as_deref().unwrap_or(""),
+                    next_attempt = ?status.next_attempt_after,
+                    "Issuance failed, backing off"
+                );
+            }
+        }
+
+        if let Err(e) = self.storage.save_status(&cert_config.name, &status).await {
+            warn!(
+                name = cert_config.name,
+                error = %e,
+                "Failed to save issuance status"
+            );
+        }
+
+        result
     }
 
     /// Renew a specific certificate
     async fn renew_certificate(&self, cert_config: &CertificateConfig) -> Result<()> {
-        let account = self.account.read().await;
-        let xds_state = self.xds_state.clone();
+        let ip_addresses = Self::ip_addresses(cert_config)?;
+        let stored = if cert_config.reuse_key {
+            self.storage.load_certificate(&cert_config.name).await?
+        } else {
+            None
+        };
+        let (key_pair, key_created) = signing_key(cert_config, stored.as_ref(), Utc::now())?;
+        let issuer = self.accounts.get(cert_config.issuer_name())?;
+        if let Some(preflight) = &issuer.preflight {
+            self.preflight(cert_config, preflight, &ip_addresses)
+                .await?;
+        }
+        issuer.new_orders.acquire(cert_config.issuer_name()).await;
+        let account = issuer.account.read().await;
 
-        let (cert_chain_pem, private_key_pem, _) = CertificateOrder::order(
+        let (cert_chain_pem, private_key_pem) = CertificateOrder::order(
             &account,
+            &self.accounts,
+            &self.storage,
             &cert_config.name,
-            &cert_config.domains,
-            &self.challenge_state,
-            move || {
-                // Trigger xDS rebuild when challenges are ready
-                xds_state.notify_change();
+            &CertificateRequest {
+                issuer: cert_config.issuer_name(),
+                kind: OrderKind::Production,
+                domains: &cert_config.domains,
+                ip_addresses: &ip_addresses,
+                key_pair: &key_pair,
+                csr: &cert_config.csr,
             },
+            &self.challenge_state,
+            &self.publisher,
         )
         .await?;
 
@@ -146,58 +415,678 @@ impl RenewalManager {
             cert_chain_pem: cert_chain_pem.clone(),
             private_key_pem: private_key_pem.clone(),
             domains: cert_config.domains.clone(),
+            ip_addresses,
             not_after,
+            issuer: Some(cert_config.issuer_name().to_string()),
+            directory_url: Some(issuer.directory_url.clone()),
+            key_created: Some(key_created),
         };
 
         self.storage
             .save_certificate(&cert_config.name, &stored_cert)
             .await?;
 
-        // Update xDS state
+        // Update xDS state, unless a reload removed the certificate meanwhile
+        if self.is_managed(&cert_config.name) {
+            self.xds_state
+                .update_secret(&cert_config.name, cert_chain_pem, private_key_pem)
+                .await;
+        }
+
+        info!(name = cert_config.name, "Certificate renewed successfully");
+
+        Ok(())
+    }
+
+    /// Order the certificate from the issuer's preflight directory before the first
+    /// production order for its identifiers
+    ///
+    /// Skipped when the stored certificate already covers the same identifiers, or a
+    /// preflight order for them passed before. The preflight certificate is discarded.
+    async fn preflight(
+        &self,
+        cert_config: &CertificateConfig,
+        preflight: &IssuerAccount,
+        ip_addresses: &[IpAddr],
+    ) -> Result<()> {
+        let name = cert_config.name.as_str();
+        let identifiers = identifier_set(&cert_config.domains, ip_addresses);
+
+        // Certificates stored before the issuer was recorded are assumed to match
+        let issued = self
+            .storage
+            .load_certificate(name)
+            .await?
+            .is_some_and(|cert| {
+                cert.issuer
+                    .as_deref()
+                    .is_none_or(|issuer| issuer == cert_config.issuer_name())
+                    && identifier_set(&cert.domains, &cert.ip_addresses) == identifiers
+            });
+        if issued {
+            debug!(name, "Identifiers already issued, skipping preflight");
+            return Ok(());
+        }
+
+        let mut results = self.storage.load_preflight_results(name).await?;
+        let cached = |result: &PreflightResult| {
+            result.directory_url == preflight.directory_url && result.identifiers == identifiers
+        };
+        if let Some(result) = results.iter().find(|r| cached(r)) {
+            debug!(name, passed = %result.passed, "Preflight already passed");
+            return Ok(());
+        }
+
+        info!(
+            name,
+            directory_url = preflight.directory_url,
+            "Running preflight order"
+        );
+        let key_pair = KeyPair::generate_for(key_algorithm(cert_config.key_type))?;
+        preflight.new_orders.acquire(&preflight.name).await;
+        let account = preflight.account.read().await;
+        CertificateOrder::order(
+            &account,
+            &self.accounts,
+            &self.storage,
+            name,
+            &CertificateRequest {
+                issuer: &preflight.name,
+                kind: OrderKind::Preflight,
+                domains: &cert_config.domains,
+                ip_addresses,
+                key_pair: &key_pair,
+                csr: &cert_config.csr,
+            },
+            &self.challenge_state,
+            &self.publisher,
+        )
+        .await
+        .map_err(|e| Error::Preflight {
+            directory_url: preflight.directory_url.clone(),
+            source: Box::new(e),
+        })?;
+
+        results.retain(|r| !cached(r));
+        results.push(PreflightResult {
+            directory_url: preflight.directory_url.clone(),
+            identifiers,
+            passed: Utc::now(),
+        });
+        self.storage.save_preflight_results(name, &results).await?;
+
+        info!(name, "Preflight order passed, proceeding with issuance");
+        Ok(())
+    }
+
+    /// Configured IP addresses of a certificate plus the detected ones, if enabled
+    fn ip_addresses(cert_config: &CertificateConfig) -> Result<Vec<IpAddr>> {
+        let mut ip_addresses = cert_config.ip_addresses.clone();
+
+        if cert_config.detect_ip_addresses {
+            let detected = detect_ip_addresses();
+            debug!(name = cert_config.name, ?detected, "Detected IP addresses");
+            for ip in detected {
+                if !ip_addresses.contains(&ip) {
+                    ip_addresses.push(ip);
+                }
+            }
+        }
+
+        if cert_config.domains.is_empty() && ip_addresses.is_empty() {
+            return Err(Error::Config(format!(
+                "Certificate '{}' has no domains and no IP addresses were detected",
+                cert_config.name
+            )));
+        }
+
+        Ok(ip_addresses)
+    }
+
+    /// Serve a secret for every configured certificate before Envoy connects
+    ///
+    /// Stored certificates that have not expired are served as they are. The others get a
+    /// self-signed placeholder, so listeners referencing them can warm while issuance is
+    /// pending.
+    pub async fn serve_initial_secrets(&self) {
+        for cert_config in &self.certificates() {
+            self.serve_initial_secret(cert_config).await;
+        }
+    }
+
+    /// Serve the stored certificate, or a placeholder if there is no unexpired one
+    async fn serve_initial_secret(&self, cert_config: &CertificateConfig) {
+        match self.storage.load_certificate(&cert_config.name).await {
+            Ok(Some(cert)) if cert.not_after > Utc::now() => {
+                info!(
+                    name = cert_config.name,
+                    days_until_expiry = (cert.not_after - Utc::now()).num_days(),
+                    "Loading existing certificate"
+                );
+                self.xds_state
+                    .update_secret(&cert_config.name, cert.cert_chain_pem, cert.private_key_pem)
+                    .await;
+                // A placeholder from a previous run is no longer served
+                if let Err(e) = self.record_placeholder(&cert_config.name, false).await {
+                    warn!(
+                        name = cert_config.name,
+                        error = %e,
+                        "Failed to save issuance status"
+                    );
+                }
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                name = cert_config.name,
+                error = %e,
+                "Failed to load stored certificate"
+            ),
+        }
+
+        if let Err(e) = self.serve_placeholder(cert_config).await {
+            error!(
+                name = cert_config.name,
+                error = %e,
+                "Failed to serve placeholder certificate"
+            );
+        }
+    }
+
+    /// Serve a self-signed placeholder and flag it in the certificate's status
+    async fn serve_placeholder(&self, cert_config: &CertificateConfig) -> Result<()> {
+        let ip_addresses = Self::ip_addresses(cert_config)?;
+        let (cert_chain_pem, private_key_pem) =
+            placeholder_certificate(cert_config, &ip_addresses)?;
         self.xds_state
             .update_secret(&cert_config.name, cert_chain_pem, private_key_pem)
             .await;
+        warn!(
+            name = cert_config.name,
+            "Serving self-signed placeholder certificate until issuance completes"
+        );
 
-        info!(name = cert_config.name, "Certificate renewed successfully");
+        self.record_placeholder(&cert_config.name, true).await
+    }
 
-        Ok(())
+    /// Record in the certificate's status whether a placeholder is served for it
+    async fn record_placeholder(&self, name: &str, serving: bool) -> Result<()> {
+        let mut status = self.storage.load_status(name).await?;
+        if status.serving_placeholder == serving {
+            return Ok(());
+        }
+        status.serving_placeholder = serving;
+        self.storage.save_status(name, &status).await
     }
 
     /// Initial certificate issuance for all configured certificates
+    ///
+    /// Expects `serve_initial_secrets` to have served the stored certificates already.
     pub async fn initial_issuance(&self) -> Result<()> {
         info!("Performing initial certificate check/issuance");
 
-        for cert_config in &self.certificates {
-            // Check if certificate exists and is valid
-            if let Ok(Some(cert)) = self.storage.load_certificate(&cert_config.name).await {
-                let now = Utc::now();
-                let days_until_expiry = (cert.not_after - now).num_days();
-
-                if days_until_expiry > 0 {
-                    info!(
-                        name = cert_config.name,
-                        days_until_expiry, "Loading existing certificate"
-                    );
+        let certificates = self.certificates();
+        let mut to_issue = Vec::new();
 
-                    // Load into xDS state
-                    self.xds_state
-                        .update_secret(&cert_config.name, cert.cert_chain_pem, cert.private_key_pem)
-                        .await;
-                    continue;
-                }
+        for cert_config in &certificates {
+            // Keep certificates that are valid and match their configuration
+            if let Ok(Some(cert)) = self.storage.load_certificate(&cert_config.name).await
+                && (cert.not_after - Utc::now()).num_days() > 0
+                && self.matches_config(cert_config, &cert)
+            {
+                continue;
             }
 
-            // Certificate doesn't exist or is expired, issue new one
+            to_issue.push(cert_config);
+        }
+
+        // Certificates that don't exist, are expired or changed are issued concurrently
+        self.for_each_order(to_issue, |cert_config| async move {
             info!(name = cert_config.name, "Issuing new certificate");
-            if let Err(e) = self.renew_certificate(cert_config).await {
+            if let Err(e) = self.attempt_issuance(cert_config).await {
                 error!(
                     name = cert_config.name,
                     error = %e,
                     "Failed to issue certificate on startup"
                 );
             }
-        }
+        })
+        .await;
+
+        self.refresh_ocsp_staples().await;
 
         Ok(())
     }
+
+    /// Replace the managed certificates after a configuration reload
+    ///
+    /// Removed certificates are no longer renewed, and their secrets are withdrawn unless
+    /// a static certificate in `static_names` now serves them. Added certificates get a
+    /// placeholder until issued. Returns the names of the added or changed certificates,
+    /// to be passed to [`Self::issue_updated`].
+    pub async fn update_certificates(
+        &self,
+        certificates: Vec<CertificateConfig>,
+        static_names: &[&str],
+    ) -> Vec<String> {
+        let previous = std::mem::replace(
+            &mut *self.certificates.write().unwrap(),
+            certificates.clone(),
+        );
+
+        for removed in previous
+            .iter()
+            .filter(|old| !certificates.iter().any(|c| c.name == old.name))
+        {
+            info!(
+                name = removed.name,
+                "Certificate removed from configuration, no longer renewing"
+            );
+            if !static_names.contains(&removed.name.as_str()) {
+                self.xds_state.remove_secret(&removed.name).await;
+            }
+        }
+
+        let mut updated = Vec::new();
+        for cert_config in certificates.iter().filter(|c| !previous.contains(c)) {
+            if previous.iter().any(|old| old.name == cert_config.name) {
+                info!(name = cert_config.name, "Certificate configuration changed");
+                if !cert_config.ocsp_stapling {
+                    self.xds_state
+                        .update_ocsp_staple(&cert_config.name, None)
+                        .await;
+                }
+            } else {
+                info!(
+                    name = cert_config.name,
+                    "Certificate added to configuration"
+                );
+                self.serve_initial_secret(cert_config).await;
+            }
+            updated.push(cert_config.name.clone());
+        }
+        updated
+    }
+
+    /// Issue the certificates a reload added or changed, if needed
+    ///
+    /// Runs for successive reloads are serialized, and each uses the current configuration
+    /// of the certificates, so a later reload changing or removing one in the meantime wins.
+    pub async fn issue_updated(&self, names: Vec<String>) {
+        let _serialized = self.reload_issuance.lock().await;
+
+        let updated: Vec<CertificateConfig> = self
+            .certificates()
+            .into_iter()
+            .filter(|c| names.contains(&c.name))
+            .collect();
+        self.for_each_order(&updated, |cert_config| {
+            self.check_and_renew_one(cert_config)
+        })
+        .await;
+
+        self.refresh_ocsp_staples().await;
+    }
+}
+
+/// Self-signed certificate served until the real one is issued
+///
+/// It covers the configured domains and the configured and detected IP addresses so
+/// SNI-based filter chains match, and its common name marks it as a placeholder for anyone
+/// inspecting it.
+fn placeholder_certificate(
+    cert_config: &CertificateConfig,
+    ip_addresses: &[IpAddr],
+) -> Result<(String, String)> {
+    let key_pair = KeyPair::generate_for(key_algorithm(cert_config.key_type))?;
+
+    let mut params = CertificateParams::new(cert_config.domains.clone())?;
+    params
+        .subject_alt_names
+        .extend(ip_addresses.iter().map(|ip| SanType::IpAddress(*ip)));
+    params.distinguished_name.push(
+        DnType::CommonName,
+        format!("envoy-acme-xds placeholder for {}", cert_config.name),
+    );
+
+    let cert = params.self_signed(&key_pair)?;
+    Ok((cert.pem(), key_pair.serialize_pem()))
+}
+
+/// Private key for the next CSR and when it was generated
+///
+/// With `reuse_key` the stored key is used until its rotation is due, unless it no longer
+/// matches the configured key type. Otherwise a new key is generated.
+fn signing_key(
+    cert_config: &CertificateConfig,
+    stored: Option<&StoredCert>,
+    now: DateTime<Utc>,
+) -> Result<(KeyPair, DateTime<Utc>)> {
+    let algorithm = key_algorithm(cert_config.key_type);
+
+    if cert_config.reuse_key
+        && let Some(cert) = stored
+        && !key_rotation_due(cert_config, cert, now)
+    {
+        match KeyPair::from_pem(&cert.private_key_pem) {
+            Ok(key_pair) if key_pair.algorithm() == algorithm => {
+                debug!(name = cert_config.name, "Reusing stored private key");
+                // Keys stored before creation times were recorded start their rotation period now
+                return Ok((key_pair, cert.key_created.unwrap_or(now)));
+            }
+            Ok(_) => info!(
+                name = cert_config.name,
+                key_type = %cert_config.key_type,
+                "Stored private key has a different type, generating a new one"
+            ),
+            Err(e) => warn!(
+                name = cert_config.name,
+                error = %e,
+                "Failed to load stored private key, generating a new one"
+            ),
+        }
+    }
+
+    Ok((KeyPair::generate_for(algorithm)?, now))
+}
+
+/// Whether a reused key has reached its scheduled rotation
+fn key_rotation_due(
+    cert_config: &CertificateConfig,
+    cert: &StoredCert,
+    now: DateTime<Utc>,
+) -> bool {
+    cert_config.reuse_key
+        && cert.key_created.is_some_and(|created| {
+            now - created >= chrono::Duration::days(i64::from(cert_config.key_rotation_days))
+        })
+}
+
+/// Describe how a stored certificate differs from what its configuration asks for
+fn describe_changes(
+    cert_config: &CertificateConfig,
+    ip_addresses: &[IpAddr],
+    directory_url: &str,
+    cert: &StoredCert,
+    details: &CertificateDetails,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    let configured: BTreeSet<String> = cert_config
+        .domains
+        .iter()
+        .map(|d| d.to_ascii_lowercase())
+        .collect();
+    let issued: BTreeSet<String> = details
+        .dns_names
+        .iter()
+        .map(|d| d.to_ascii_lowercase())
+        .collect();
+    changes.extend(describe_set_change("domains", &configured, &issued));
+
+    let configured: BTreeSet<IpAddr> = ip_addresses.iter().copied().collect();
+    let issued: BTreeSet<IpAddr> = details.ip_addresses.iter().copied().collect();
+    changes.extend(describe_set_change("IP addresses", &configured, &issued));
+
+    if details.key_type != Some(cert_config.key_type) {
+        let issued = details
+            .key_type
+            .map_or_else(|| "unsupported".to_string(), |k| k.to_string());
+        changes.push(format!("key type {issued} -> {}", cert_config.key_type));
+    }
+
+    if details.must_staple != cert_config.csr.must_staple {
+        changes.push(format!(
+            "must-staple {} -> {}",
+            details.must_staple, cert_config.csr.must_staple
+        ));
+    }
+
+    // Certificates stored before the issuer was recorded are assumed to match
+    if let Some(issuer) = &cert.issuer
+        && issuer != cert_config.issuer_name()
+    {
+        changes.push(format!("issuer {issuer} -> {}", cert_config.issuer_name()));
+    }
+    if let Some(stored_url) = &cert.directory_url
+        && stored_url != directory_url
+    {
+        changes.push(format!("ACME directory {stored_url} -> {directory_url}"));
+    }
+
+    changes
+}
+
+/// Sorted, lowercased domains and IP addresses identifying a certificate's order
+fn identifier_set(domains: &[String], ip_addresses: &[IpAddr]) -> Vec<String> {
+    let identifiers: BTreeSet<String> = domains
+        .iter()
+        .map(|d| d.to_ascii_lowercase())
+        .chain(ip_addresses.iter().map(|ip| ip.to_string()))
+        .collect();
+    identifiers.into_iter().collect()
+}
+
+/// Describe the entries added to and removed from a certificate's identifiers
+fn describe_set_change<T: Ord + Display>(
+    what: &str,
+    configured: &BTreeSet<T>,
+    issued: &BTreeSet<T>,
+) -> Option<String> {
+    let join = |items: Vec<&T>| {
+        items
+            .iter()
+            .map(|item| item.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let added = join(configured.difference(issued).collect());
+    let removed = join(issued.difference(configured).collect());
+
+    match (added.is_empty(), removed.is_empty()) {
+        (true, true) => None,
+        (false, true) => Some(format!("{what} added: {added}")),
+        (true, false) => Some(format!("{what} removed: {removed}")),
+        (false, false) => Some(format!("{what} added: {added}, removed: {removed}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn manager(storage_dir: &str, max_concurrent_orders: usize) -> RenewalManager {
+        let xds_state = XdsState::new();
+        RenewalManager::new(
+            Arc::new(CertificateStorage::new(
+                std::env::temp_dir().join(storage_dir),
+            )),
+            IssuerAccounts::default(),
+            ChallengeState::new(),
+            xds_state.clone(),
+            ChallengePublisher::new(xds_state, Duration::from_secs(1), None, false),
+            Vec::new(),
+            max_concurrent_orders,
+        )
+    }
+
+    /// Counts orders and tracks how many run at once
+    #[derive(Default)]
+    struct Orders {
+        started: AtomicUsize,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    impl Orders {
+        async fn run(&self) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn self_signed(domains: &[&str], ip_addresses: &[IpAddr]) -> String {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(
+            domains.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        params
+            .subject_alt_names
+            .extend(ip_addresses.iter().map(|ip| rcgen::SanType::IpAddress(*ip)));
+        params.self_signed(&key_pair).unwrap().pem()
+    }
+
+    fn cert_config(yaml: &str) -> CertificateConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn stored(pem: &str, issuer: &str) -> StoredCert {
+        StoredCert {
+            cert_chain_pem: pem.to_string(),
+            private_key_pem: String::new(),
+            domains: Vec::new(),
+            ip_addresses: Vec::new(),
+            not_after: Utc::now(),
+            issuer: Some(issuer.to_string()),
+            directory_url: Some("https://acme.test/directory".to_string()),
+            key_created: None,
+        }
+    }
+
+    #[test]
+    fn test_describe_changes() {
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        let pem = self_signed(&["example.com", "old.example.com"], &[ip]);
+        let details = parse_certificate_details(&pem).unwrap();
+        assert_eq!(details.key_type, Some(crate::config::KeyType::EcdsaP256));
+
+        let unchanged = cert_config("name: a\ndomains: [Example.com, old.example.com]");
+        assert!(
+            describe_changes(
+                &unchanged,
+                &[ip],
+                "https://acme.test/directory",
+                &stored(&pem, "default"),
+                &details,
+            )
+            .is_empty()
+        );
+
+        let changed = cert_config(
+            "name: a\ndomains: [example.com, www.example.com]\nkey_type: ecdsa-p384\nissuer: internal",
+        );
+        assert_eq!(
+            describe_changes(
+                &changed,
+                &[],
+                "https://ca.internal/directory",
+                &stored(&pem, "default"),
+                &details,
+            ),
+            vec![
+                "domains added: www.example.com, removed: old.example.com",
+                "IP addresses removed: 192.0.2.10",
+                "key type ecdsa-p256 -> ecdsa-p384",
+                "issuer default -> internal",
+                "ACME directory https://acme.test/directory -> https://ca.internal/directory",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signing_key_reuse_and_rotation() {
+        let now = Utc::now();
+        let config =
+            cert_config("name: a\ndomains: [example.com]\nreuse_key: true\nkey_rotation_days: 90");
+        let key_pair = KeyPair::generate().unwrap();
+        let mut cert = stored("", "default");
+        cert.private_key_pem = key_pair.serialize_pem();
+        cert.key_created = Some(now - chrono::Duration::days(30));
+
+        let (reused, created) = signing_key(&config, Some(&cert), now).unwrap();
+        assert_eq!(reused.public_key_der(), key_pair.public_key_der());
+        assert_eq!(created, cert.key_created.unwrap());
+        assert!(!key_rotation_due(&config, &cert, now));
+
+        cert.key_created = Some(now - chrono::Duration::days(90));
+        assert!(key_rotation_due(&config, &cert, now));
+        let (rotated, created) = signing_key(&config, Some(&cert), now).unwrap();
+        assert_ne!(rotated.public_key_der(), key_pair.public_key_der());
+        assert_eq!(created, now);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_order_limit() {
+        let manager = manager("envoy-acme-xds-order-limit", 2);
+        let certificates: Vec<CertificateConfig> = (0..5)
+            .map(|i| cert_config(&format!("name: cert-{i}\ndomains: [{i}.example.com]")))
+            .collect();
+
+        let orders = Orders::default();
+        manager
+            .for_each_order(&certificates, |_| orders.run())
+            .await;
+        assert_eq!(orders.started.load(Ordering::SeqCst), 5);
+        assert_eq!(orders.max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_issuance_in_progress_guard() {
+        let manager = manager("envoy-acme-xds-in-progress", 4);
+        let orders = Orders::default();
+        let issue = || async {
+            orders.run().await;
+            Ok(())
+        };
+
+        // The renewal loop and a reload asking for the same certificate issue it once
+        let (a, again, b) = tokio::join!(
+            manager.exclusive("a", issue()),
+            manager.exclusive("a", issue()),
+            manager.exclusive("b", issue()),
+        );
+        assert!(a.is_ok() && again.is_ok() && b.is_ok());
+        assert_eq!(orders.started.load(Ordering::SeqCst), 2);
+
+        // The guard is released once the issuance completes, even if it failed
+        let failed = manager
+            .exclusive("a", async {
+                Err(Error::ChallengeFailed("test".to_string()))
+            })
+            .await;
+        assert!(failed.is_err());
+        manager.exclusive("a", issue()).await.unwrap();
+        assert_eq!(orders.started.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_placeholder_certificate() {
+        let config = cert_config(
+            "name: site\ndomains: [example.com, www.example.com]\nip_addresses: [192.0.2.10]",
+        );
+        let detected: IpAddr = "2001:db8::10".parse().unwrap();
+        let ip_addresses = [config.ip_addresses[0], detected];
+
+        let (cert_pem, key_pem) = placeholder_certificate(&config, &ip_addresses).unwrap();
+        assert!(KeyPair::from_pem(&key_pem).is_ok());
+
+        let details = parse_certificate_details(&cert_pem).unwrap();
+        assert_eq!(details.dns_names, ["example.com", "www.example.com"]);
+        assert_eq!(details.ip_addresses, ip_addresses);
+        assert_eq!(details.key_type, Some(crate::config::KeyType::EcdsaP256));
+
+        let (_, pem) = x509_parser::pem::parse_x509_pem(cert_pem.as_bytes()).unwrap();
+        let cert = pem.parse_x509().unwrap();
+        let common_name = cert.subject().iter_common_name().next().unwrap();
+        assert_eq!(
+            common_name.as_str().unwrap(),
+            "envoy-acme-xds placeholder for site"
+        );
+    }
 }
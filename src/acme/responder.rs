@@ -0,0 +1,184 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+
+use crate::error::{Error, Result};
+
+use super::challenge::ChallengeState;
+
+/// Path prefix of HTTP-01 challenge requests
+const CHALLENGE_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Pause after a failed accept, so running out of file descriptors does not spin the loop
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Time a client has to send a request's headers, also while a kept-alive connection idles
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Built-in HTTP server answering HTTP-01 challenges straight from `ChallengeState`
+///
+/// Lets certificates be issued before Envoy connects.
+pub struct ChallengeResponder {
+    listener: TcpListener,
+    challenge_state: ChallengeState,
+}
+
+impl ChallengeResponder {
+    /// Bind the responder to its address
+    pub async fn bind(address: SocketAddr, challenge_state: ChallengeState) -> Result<Self> {
+        let listener = TcpListener::bind(address).await.map_err(|source| {
+            Error::Config(format!(
+                "Failed to bind challenge responder to {address}: {source}"
+            ))
+        })?;
+        info!(%address, "Challenge responder listening");
+
+        Ok(Self {
+            listener,
+            challenge_state,
+        })
+    }
+
+    /// Accept connections until the task is dropped
+    pub async fn run(self) {
+        loop {
+            let (stream, peer) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "Failed to accept challenge responder connection");
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+
+            let challenge_state = self.challenge_state.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let challenge_state = challenge_state.clone();
+                    async move { Ok::<_, Infallible>(respond(&challenge_state, request).await) }
+                });
+                if let Err(e) = http1::Builder::new()
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(HEADER_READ_TIMEOUT)
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    debug!(%peer, error = %e, "Challenge responder connection failed");
+                }
+            });
+        }
+    }
+}
+
+/// Answer a single challenge request
+async fn respond(
+    challenge_state: &ChallengeState,
+    request: Request<Incoming>,
+) -> Response<Full<Bytes>> {
+    let key_authorization =
+        match lookup(challenge_state, request.method(), request.uri().path()).await {
+            Ok(key_authorization) => key_authorization,
+            Err(status) => {
+                debug!(
+                    method = %request.method(),
+                    path = request.uri().path(),
+                    %status,
+                    "Challenge responder rejected request"
+                );
+                return response(status, Bytes::new());
+            }
+        };
+
+    debug!(
+        path = request.uri().path(),
+        "Challenge responder served challenge"
+    );
+    let body = if request.method() == Method::HEAD {
+        Bytes::new()
+    } else {
+        Bytes::from(key_authorization)
+    };
+    response(StatusCode::OK, body)
+}
+
+/// Key authorization for a request, or the status to reject it with
+async fn lookup(
+    challenge_state: &ChallengeState,
+    method: &Method,
+    path: &str,
+) -> std::result::Result<String, StatusCode> {
+    let token = path
+        .strip_prefix(CHALLENGE_PATH_PREFIX)
+        .filter(|token| !token.is_empty() && !token.contains('/'))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if method != Method::GET && method != Method::HEAD {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    challenge_state
+        .key_authorization(token)
+        .await
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn response(status: StatusCode, body: Bytes) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acme::ActiveChallenge;
+
+    #[tokio::test]
+    async fn test_lookup() {
+        let challenge_state = ChallengeState::new();
+        challenge_state
+            .add(ActiveChallenge {
+                token: "abc".to_string(),
+                key_authorization: "abc.thumbprint".to_string(),
+                cert_name: "example".to_string(),
+                identifier: "example.com".to_string(),
+            })
+            .await;
+
+        let path = "/.well-known/acme-challenge/abc";
+        assert_eq!(
+            lookup(&challenge_state, &Method::GET, path).await,
+            Ok("abc.thumbprint".to_string())
+        );
+        assert_eq!(
+            lookup(&challenge_state, &Method::POST, path).await,
+            Err(StatusCode::METHOD_NOT_ALLOWED)
+        );
+        assert_eq!(
+            lookup(
+                &challenge_state,
+                &Method::GET,
+                "/.well-known/acme-challenge/other"
+            )
+            .await,
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            lookup(&challenge_state, &Method::GET, "/index.html").await,
+            Err(StatusCode::NOT_FOUND)
+        );
+    }
+}
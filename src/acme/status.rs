@@ -0,0 +1,185 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use instant_acme::{Identifier, Problem};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Delay after the first failed attempt; doubled for each further failure
+const BASE_BACKOFF_MINUTES: i64 = 5;
+
+/// Upper bound for the exponential backoff
+const MAX_BACKOFF_HOURS: i64 = 24;
+
+/// Persisted outcome of issuance attempts for a certificate
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IssuanceStatus {
+    /// Consecutive failed attempts since the last success
+    pub failed_attempts: u32,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// ACME problem type of the last failure, if the CA reported one
+    pub last_problem_type: Option<String>,
+    /// Full problem document of the last failure, if the CA reported one
+    pub last_problem: Option<ProblemDetails>,
+    /// Earliest time another attempt may be made
+    pub next_attempt_after: Option<DateTime<Utc>>,
+    /// A self-signed placeholder is served because no certificate has been issued yet
+    pub serving_placeholder: bool,
+}
+
+/// Persisted copy of an ACME problem document
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: Option<String>,
+    pub detail: Option<String>,
+    pub status: Option<u16>,
+    pub subproblems: Vec<SubproblemDetails>,
+}
+
+/// Persisted copy of a problem concerning a single identifier
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubproblemDetails {
+    pub identifier: Option<Identifier>,
+    #[serde(rename = "type")]
+    pub problem_type: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl From<&Problem> for ProblemDetails {
+    fn from(problem: &Problem) -> Self {
+        Self {
+            problem_type: problem.r#type.clone(),
+            detail: problem.detail.clone(),
+            status: problem.status,
+            subproblems: problem
+                .subproblems
+                .iter()
+                .map(|subproblem| SubproblemDetails {
+                    identifier: subproblem.identifier.clone(),
+                    problem_type: subproblem.r#type.clone(),
+                    detail: subproblem.detail.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl IssuanceStatus {
+    /// Returns the time until which attempts are suppressed, if still in the future
+    pub fn backoff_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.next_attempt_after.filter(|next| *next > now)
+    }
+
+    /// Reset failure and placeholder state after a successful attempt
+    pub fn record_success(&mut self, now: DateTime<Utc>) {
+        *self = Self {
+            last_attempt: Some(now),
+            ..Self::default()
+        };
+    }
+
+    /// Record a failed attempt and schedule the next allowed attempt
+    ///
+    /// The next attempt is delayed by an exponential backoff, or until the time the CA
+    /// asked us to retry after if that is later.
+    pub fn record_failure(&mut self, error: &Error, now: DateTime<Utc>) {
+        self.failed_attempts = self.failed_attempts.saturating_add(1);
+        self.last_attempt = Some(now);
+        self.last_error = Some(error.to_string());
+        self.last_problem_type = error.acme_problem().and_then(|p| p.r#type.clone());
+        self.last_problem = error.acme_problem().map(ProblemDetails::from);
+
+        let next = now + backoff_delay(self.failed_attempts);
+        self.next_attempt_after = Some(match retry_after(error) {
+            Some(retry_after) => next.max(retry_after),
+            None => next,
+        });
+    }
+}
+
+/// Exponential backoff for the given number of consecutive failures
+fn backoff_delay(failed_attempts: u32) -> Duration {
+    let exponent = failed_attempts.saturating_sub(1).min(16);
+    let delay = Duration::minutes(BASE_BACKOFF_MINUTES * 2_i64.pow(exponent));
+    delay.min(Duration::hours(MAX_BACKOFF_HOURS))
+}
+
+/// Extract the retry time suggested by the CA
+///
+/// instant-acme does not expose the `Retry-After` header of error responses, but Let's
+/// Encrypt repeats it in rate limit problem details as "retry after 2006-01-02 15:04:05 UTC".
+fn retry_after(error: &Error) -> Option<DateTime<Utc>> {
+    let detail = error.acme_problem()?.detail.as_deref()?;
+    let (_, rest) = detail.split_once("retry after ")?;
+    let (timestamp, _) = NaiveDateTime::parse_and_remainder(rest, "%Y-%m-%d %H:%M:%S UTC").ok()?;
+    Some(timestamp.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limited(detail: &str) -> Error {
+        let problem: instant_acme::Problem = serde_json::from_value(serde_json::json!({
+            "type": "urn:ietf:params:acme:error:rateLimited",
+            "detail": detail,
+            "status": 429,
+        }))
+        .unwrap();
+        Error::Acme(instant_acme::Error::Api(problem))
+    }
+
+    #[test]
+    fn test_backoff_grows_exponentially_and_caps() {
+        assert_eq!(backoff_delay(1), Duration::minutes(5));
+        assert_eq!(backoff_delay(2), Duration::minutes(10));
+        assert_eq!(backoff_delay(4), Duration::minutes(40));
+        assert_eq!(backoff_delay(50), Duration::hours(MAX_BACKOFF_HOURS));
+    }
+
+    #[test]
+    fn test_failure_honors_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2024-11-14T20:00:00Z")
+            .unwrap()
+            .to_utc();
+        let error = rate_limited(
+            "too many failed authorizations (5) for \"example.com\" in the last 1h0m0s, \
+             retry after 2024-11-14 21:12:22 UTC: see https://letsencrypt.org/docs/rate-limits/",
+        );
+
+        let mut status = IssuanceStatus::default();
+        status.record_failure(&error, now);
+
+        assert_eq!(status.failed_attempts, 1);
+        assert_eq!(
+            status.last_problem_type.as_deref(),
+            Some("urn:ietf:params:acme:error:rateLimited")
+        );
+        let problem = status.last_problem.as_ref().unwrap();
+        assert_eq!(problem.status, Some(429));
+        assert!(
+            problem
+                .detail
+                .as_deref()
+                .unwrap()
+                .contains("too many failed")
+        );
+        assert_eq!(
+            status.next_attempt_after,
+            Some(
+                DateTime::parse_from_rfc3339("2024-11-14T21:12:22Z")
+                    .unwrap()
+                    .to_utc()
+            )
+        );
+        assert!(status.backoff_until(now).is_some());
+
+        status.record_success(now);
+        assert_eq!(status.failed_attempts, 0);
+        assert!(status.backoff_until(now).is_none());
+    }
+}
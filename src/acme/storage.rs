@@ -1,11 +1,16 @@
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 use chrono::{DateTime, Utc};
-use instant_acme::AccountCredentials;
+use instant_acme::{AccountCredentials, Identifier};
 use serde::{Deserialize, Serialize};
 
+use crate::config::{DEFAULT_ISSUER, KeyType};
 use crate::error::{Error, Result};
 
+use super::challenge::ActiveChallenge;
+use super::status::IssuanceStatus;
+
 /// Manages filesystem storage for ACME account and certificates
 pub struct CertificateStorage {
     base_dir: PathBuf,
@@ -17,14 +22,74 @@ pub struct StoredCert {
     pub cert_chain_pem: String,
     pub private_key_pem: String,
     pub domains: Vec<String>,
+    pub ip_addresses: Vec<IpAddr>,
     pub not_after: DateTime<Utc>,
+    /// Issuer name and ACME directory the certificate was obtained from
+    pub issuer: Option<String>,
+    pub directory_url: Option<String>,
+    /// When the private key was generated, if recorded
+    pub key_created: Option<DateTime<Utc>>,
+}
+
+/// Identifiers and key type read from a certificate itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateDetails {
+    pub dns_names: Vec<String>,
+    pub ip_addresses: Vec<IpAddr>,
+    /// `None` for key algorithms that cannot be configured
+    pub key_type: Option<KeyType>,
+    /// Whether the TLS Feature (Must-Staple) extension is present
+    pub must_staple: bool,
+}
+
+/// Which of a certificate's orders a pending order record belongs to
+///
+/// Preflight orders are recorded separately, so one interrupted by a restart does not
+/// replace the record of the production order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderKind {
+    Production,
+    Preflight,
+}
+
+/// An ACME order in progress, recorded so it can be resumed after a restart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingOrder {
+    pub url: String,
+    pub issuer: String,
+    pub identifiers: Vec<Identifier>,
+    pub created: DateTime<Utc>,
+    /// Challenges published for the order's pending authorizations
+    #[serde(default)]
+    pub challenges: Vec<ActiveChallenge>,
+    /// Key of the CSR, recorded before the order is finalized
+    #[serde(default)]
+    pub private_key_pem: Option<String>,
+}
+
+/// A successful preflight order for a set of identifiers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreflightResult {
+    /// Preflight directory the order was placed with
+    pub directory_url: String,
+    /// Sorted, lowercased domains and IP addresses of the order
+    pub identifiers: Vec<String>,
+    pub passed: DateTime<Utc>,
 }
 
 /// Certificate metadata stored alongside the cert
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct CertMeta {
     domains: Vec<String>,
+    #[serde(default)]
+    ip_addresses: Vec<IpAddr>,
     not_after: DateTime<Utc>,
+    #[serde(default)]
+    issuer: Option<String>,
+    #[serde(default)]
+    directory_url: Option<String>,
+    #[serde(default)]
+    key_created: Option<DateTime<Utc>>,
 }
 
 impl CertificateStorage {
@@ -37,6 +102,7 @@ impl CertificateStorage {
     pub async fn init(&self) -> Result<()> {
         tokio::fs::create_dir_all(&self.base_dir).await?;
         tokio::fs::create_dir_all(self.certs_dir()).await?;
+        tokio::fs::create_dir_all(self.accounts_dir()).await?;
         Ok(())
     }
 
@@ -44,8 +110,17 @@ impl CertificateStorage {
         self.base_dir.join("certs")
     }
 
-    fn account_path(&self) -> PathBuf {
-        self.base_dir.join("account.json")
+    fn accounts_dir(&self) -> PathBuf {
+        self.base_dir.join("accounts")
+    }
+
+    /// The default issuer keeps the original `account.json` location
+    fn account_path(&self, issuer: &str) -> PathBuf {
+        if issuer == DEFAULT_ISSUER {
+            self.base_dir.join("account.json")
+        } else {
+            self.accounts_dir().join(format!("{issuer}.json"))
+        }
     }
 
     fn cert_dir(&self, name: &str) -> PathBuf {
@@ -64,9 +139,28 @@ impl CertificateStorage {
         self.cert_dir(name).join("meta.json")
     }
 
-    /// Load ACME account credentials from storage
-    pub async fn load_account(&self) -> Result<Option<AccountCredentials>> {
-        let path = self.account_path();
+    fn ocsp_path(&self, name: &str) -> PathBuf {
+        self.cert_dir(name).join("ocsp.der")
+    }
+
+    fn status_path(&self, name: &str) -> PathBuf {
+        self.cert_dir(name).join("status.json")
+    }
+
+    fn order_path(&self, name: &str, kind: OrderKind) -> PathBuf {
+        self.cert_dir(name).join(match kind {
+            OrderKind::Production => "order.json",
+            OrderKind::Preflight => "preflight-order.json",
+        })
+    }
+
+    fn preflight_path(&self, name: &str) -> PathBuf {
+        self.cert_dir(name).join("preflight.json")
+    }
+
+    /// Load ACME account credentials for an issuer from storage
+    pub async fn load_account(&self, issuer: &str) -> Result<Option<AccountCredentials>> {
+        let path = self.account_path(issuer);
         if !path.exists() {
             return Ok(None);
         }
@@ -75,10 +169,10 @@ impl CertificateStorage {
         Ok(Some(creds))
     }
 
-    /// Save ACME account credentials to storage
-    pub async fn save_account(&self, creds: &AccountCredentials) -> Result<()> {
+    /// Save ACME account credentials for an issuer to storage
+    pub async fn save_account(&self, issuer: &str, creds: &AccountCredentials) -> Result<()> {
         let content = serde_json::to_string_pretty(creds)?;
-        tokio::fs::write(self.account_path(), content).await?;
+        tokio::fs::write(self.account_path(issuer), content).await?;
         Ok(())
     }
 
@@ -101,7 +195,11 @@ impl CertificateStorage {
             cert_chain_pem,
             private_key_pem,
             domains: meta.domains,
+            ip_addresses: meta.ip_addresses,
             not_after: meta.not_after,
+            issuer: meta.issuer,
+            directory_url: meta.directory_url,
+            key_created: meta.key_created,
         }))
     }
 
@@ -113,6 +211,12 @@ impl CertificateStorage {
         // Write certificate chain
         tokio::fs::write(self.cert_path(name), &cert.cert_chain_pem).await?;
 
+        // An OCSP response for the previous certificate no longer applies
+        let ocsp_path = self.ocsp_path(name);
+        if ocsp_path.exists() {
+            tokio::fs::remove_file(&ocsp_path).await?;
+        }
+
         // Write private key with restricted permissions
         let key_path = self.key_path(name);
         tokio::fs::write(&key_path, &cert.private_key_pem).await?;
@@ -127,35 +231,205 @@ impl CertificateStorage {
         // Write metadata
         let meta = CertMeta {
             domains: cert.domains.clone(),
+            ip_addresses: cert.ip_addresses.clone(),
             not_after: cert.not_after,
+            issuer: cert.issuer.clone(),
+            directory_url: cert.directory_url.clone(),
+            key_created: cert.key_created,
         };
         let meta_content = serde_json::to_string_pretty(&meta)?;
         tokio::fs::write(self.meta_path(name), meta_content).await?;
 
         Ok(())
     }
+
+    /// Load the stored DER-encoded OCSP response for a certificate
+    pub async fn load_ocsp_response(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.ocsp_path(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(tokio::fs::read(&path).await?))
+    }
+
+    /// Save a DER-encoded OCSP response next to its certificate
+    pub async fn save_ocsp_response(&self, name: &str, der: &[u8]) -> Result<()> {
+        tokio::fs::write(self.ocsp_path(name), der).await?;
+        Ok(())
+    }
+
+    /// Load the issuance status of a certificate, defaulting to a clean status
+    pub async fn load_status(&self, name: &str) -> Result<IssuanceStatus> {
+        let path = self.status_path(name);
+        if !path.exists() {
+            return Ok(IssuanceStatus::default());
+        }
+        let content = tokio::fs::read_to_string(&path).await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Save the issuance status of a certificate
+    pub async fn save_status(&self, name: &str, status: &IssuanceStatus) -> Result<()> {
+        tokio::fs::create_dir_all(self.cert_dir(name)).await?;
+        let content = serde_json::to_string_pretty(status)?;
+        tokio::fs::write(self.status_path(name), content).await?;
+        Ok(())
+    }
+
+    /// Load the order in progress for a certificate
+    pub async fn load_pending_order(
+        &self,
+        name: &str,
+        kind: OrderKind,
+    ) -> Result<Option<PendingOrder>> {
+        let path = self.order_path(name, kind);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = tokio::fs::read_to_string(&path).await?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    /// Record the order in progress for a certificate
+    pub async fn save_pending_order(
+        &self,
+        name: &str,
+        kind: OrderKind,
+        order: &PendingOrder,
+    ) -> Result<()> {
+        tokio::fs::create_dir_all(self.cert_dir(name)).await?;
+        let path = self.order_path(name, kind);
+        let content = serde_json::to_string_pretty(order)?;
+        tokio::fs::write(&path, content).await?;
+
+        // The record may hold the private key
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let perms = std::fs::Permissions::from_mode(0o600);
+            tokio::fs::set_permissions(&path, perms).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Load the preflight orders that passed for a certificate
+    pub async fn load_preflight_results(&self, name: &str) -> Result<Vec<PreflightResult>> {
+        let path = self.preflight_path(name);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = tokio::fs::read_to_string(&path).await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Save the preflight orders that passed for a certificate
+    pub async fn save_preflight_results(
+        &self,
+        name: &str,
+        results: &[PreflightResult],
+    ) -> Result<()> {
+        tokio::fs::create_dir_all(self.cert_dir(name)).await?;
+        let content = serde_json::to_string_pretty(results)?;
+        tokio::fs::write(self.preflight_path(name), content).await?;
+        Ok(())
+    }
+
+    /// Remove the order record of a certificate, if any
+    pub async fn remove_pending_order(&self, name: &str, kind: OrderKind) -> Result<()> {
+        let path = self.order_path(name, kind);
+        if path.exists() {
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
 }
 
 /// Parse expiry date from PEM certificate
 pub fn parse_certificate_expiry(pem: &str) -> Result<DateTime<Utc>> {
-    use x509_parser::error::{PEMError, X509Error};
-    use x509_parser::prelude::*;
+    let pem = parse_pem(pem)?;
+    let cert = parse_der(&pem.contents)?;
 
-    let (_, pem_block) = parse_x509_pem(pem.as_bytes()).map_err(|e| {
+    let not_after = cert.validity().not_after;
+    let timestamp = not_after.timestamp();
+
+    DateTime::from_timestamp(timestamp, 0).ok_or(Error::X509InvalidTimestamp)
+}
+
+/// Parse the subject alternative names and key type of the leaf in a PEM chain
+pub fn parse_certificate_details(pem: &str) -> Result<CertificateDetails> {
+    use x509_parser::extensions::GeneralName;
+    use x509_parser::oid_registry::{OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384};
+
+    let pem = parse_pem(pem)?;
+    let cert = parse_der(&pem.contents)?;
+
+    let mut dns_names = Vec::new();
+    let mut ip_addresses = Vec::new();
+    if let Some(san) = cert
+        .subject_alternative_name()
+        .map_err(|source| Error::X509Parse { source })?
+    {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => dns_names.push(dns.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    if let Ok(octets) = <[u8; 4]>::try_from(*bytes) {
+                        ip_addresses.push(IpAddr::from(octets));
+                    } else if let Ok(octets) = <[u8; 16]>::try_from(*bytes) {
+                        ip_addresses.push(IpAddr::from(octets));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let algorithm = &cert.public_key().algorithm;
+    let curve = algorithm
+        .parameters
+        .as_ref()
+        .and_then(|parameters| parameters.as_oid().ok());
+    let key_type = match curve {
+        _ if algorithm.algorithm != OID_KEY_TYPE_EC_PUBLIC_KEY => None,
+        Some(curve) if curve == OID_EC_P256 => Some(KeyType::EcdsaP256),
+        Some(curve) if curve == OID_NIST_EC_P384 => Some(KeyType::EcdsaP384),
+        _ => None,
+    };
+
+    let must_staple = cert
+        .extensions()
+        .iter()
+        .any(|extension| extension.oid.to_id_string() == "1.3.6.1.5.5.7.1.24");
+
+    Ok(CertificateDetails {
+        dns_names,
+        ip_addresses,
+        key_type,
+        must_staple,
+    })
+}
+
+pub(super) fn parse_pem(pem: &str) -> Result<x509_parser::pem::Pem> {
+    use x509_parser::error::PEMError;
+
+    let (_, pem_block) = x509_parser::pem::parse_x509_pem(pem.as_bytes()).map_err(|e| {
         let pem_error = match e {
             x509_parser::nom::Err::Error(err) | x509_parser::nom::Err::Failure(err) => err,
             x509_parser::nom::Err::Incomplete(_) => PEMError::IncompletePEM,
         };
         Error::X509Pem { source: pem_error }
     })?;
+    Ok(pem_block)
+}
+
+pub(super) fn parse_der(der: &[u8]) -> Result<x509_parser::certificate::X509Certificate<'_>> {
+    use x509_parser::error::X509Error;
+    use x509_parser::prelude::FromDer;
 
     let (_, cert) =
-        X509Certificate::from_der(&pem_block.contents).map_err(|e| Error::X509Parse {
+        x509_parser::certificate::X509Certificate::from_der(der).map_err(|e| Error::X509Parse {
             source: X509Error::from(e),
         })?;
-
-    let not_after = cert.validity().not_after;
-    let timestamp = not_after.timestamp();
-
-    DateTime::from_timestamp(timestamp, 0).ok_or(Error::X509InvalidTimestamp)
+    Ok(cert)
 }
@@ -0,0 +1,237 @@
+/// Derivation of certificates from the SDS secrets that listeners reference
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+use super::types::{CertificateConfig, Config};
+
+const DOWNSTREAM_TLS_CONTEXT_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext";
+const QUIC_DOWNSTREAM_TRANSPORT_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.transport_sockets.quic.v3.QuicDownstreamTransport";
+
+/// SDS secret referenced by listeners, with the server names of the chains using it
+#[derive(Debug, PartialEq)]
+struct SecretReference {
+    name: String,
+    server_names: Vec<String>,
+    listener: String,
+}
+
+/// Add a certificate for every SDS secret referenced by a listener filter chain
+///
+/// Each derived certificate covers the `filter_chain_match.server_names` of the chains
+/// referencing it, with default settings. Secrets that have an explicit `certificates` or
+/// `static_certificates` entry are left to it, so explicit entries override. Secrets listed
+/// in `envoy.external_secrets` are provided elsewhere and skipped.
+pub fn derive_certificates(config: &mut Config) -> Result<()> {
+    let mut references: Vec<SecretReference> = Vec::new();
+    for listener in &config.envoy.listeners {
+        let listener_name = string_field(listener, "name", "name").unwrap_or("<unnamed>");
+        let chains = array_field(listener, "filter_chains", "filterChains")
+            .iter()
+            .chain(object_field(
+                listener,
+                "default_filter_chain",
+                "defaultFilterChain",
+            ));
+        for chain in chains {
+            let server_names: Vec<String> =
+                object_field(chain, "filter_chain_match", "filterChainMatch")
+                    .map(|chain_match| array_field(chain_match, "server_names", "serverNames"))
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|name| name.as_str().map(str::to_lowercase))
+                    .collect();
+
+            for name in chain_secret_names(chain) {
+                match references.iter_mut().find(|r| r.name == name) {
+                    Some(reference) => {
+                        for server_name in &server_names {
+                            if !reference.server_names.contains(server_name) {
+                                reference.server_names.push(server_name.clone());
+                            }
+                        }
+                    }
+                    None => references.push(SecretReference {
+                        name,
+                        server_names: server_names.clone(),
+                        listener: listener_name.to_string(),
+                    }),
+                }
+            }
+        }
+    }
+
+    for reference in references {
+        let explicit = config.certificates.iter().any(|c| c.name == reference.name)
+            || config
+                .static_certificates
+                .iter()
+                .any(|c| c.name == reference.name)
+            || config.envoy.external_secrets.contains(&reference.name);
+        if explicit {
+            continue;
+        }
+
+        if reference.server_names.is_empty() {
+            return Err(Error::Config(format!(
+                "Cannot derive certificate '{}' referenced by listener '{}': its filter chains have no server_names; add it to certificates",
+                reference.name, reference.listener
+            )));
+        }
+        if let Some(wildcard) = reference.server_names.iter().find(|n| n.contains('*')) {
+            return Err(Error::Config(format!(
+                "Cannot derive certificate '{}' referenced by listener '{}': wildcard server name '{}' cannot be validated over HTTP-01; add it to certificates",
+                reference.name, reference.listener, wildcard
+            )));
+        }
+
+        // Going through serde applies the same defaults as an explicit entry
+        let certificate: CertificateConfig = serde_json::from_value(serde_json::json!({
+            "name": reference.name,
+            "domains": reference.server_names,
+        }))?;
+        config.certificates.push(certificate);
+    }
+
+    Ok(())
+}
+
+/// SDS secret names of a filter chain's DownstreamTlsContext, also inside QUIC transports
+fn chain_secret_names(chain: &Value) -> Vec<String> {
+    let Some(typed_config) = object_field(chain, "transport_socket", "transportSocket")
+        .and_then(|socket| object_field(socket, "typed_config", "typedConfig"))
+    else {
+        return Vec::new();
+    };
+
+    let tls_context = match typed_config.get("@type").and_then(Value::as_str) {
+        Some(DOWNSTREAM_TLS_CONTEXT_TYPE_URL) => Some(typed_config),
+        Some(QUIC_DOWNSTREAM_TRANSPORT_TYPE_URL) => object_field(
+            typed_config,
+            "downstream_tls_context",
+            "downstreamTlsContext",
+        ),
+        _ => None,
+    };
+
+    tls_context
+        .and_then(|context| object_field(context, "common_tls_context", "commonTlsContext"))
+        .map(|common| {
+            array_field(
+                common,
+                "tls_certificate_sds_secret_configs",
+                "tlsCertificateSdsSecretConfigs",
+            )
+        })
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|config| string_field(config, "name", "name").map(str::to_string))
+        .collect()
+}
+
+// Envoy accepts both the proto and the JSON (camelCase) spelling of field names
+fn object_field<'a>(value: &'a Value, name: &str, json_name: &str) -> Option<&'a Value> {
+    value
+        .get(name)
+        .or_else(|| value.get(json_name))
+        .filter(|field| field.is_object())
+}
+
+fn array_field<'a>(value: &'a Value, name: &str, json_name: &str) -> &'a [Value] {
+    value
+        .get(name)
+        .or_else(|| value.get(json_name))
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn string_field<'a>(value: &'a Value, name: &str, json_name: &str) -> Option<&'a str> {
+    value
+        .get(name)
+        .or_else(|| value.get(json_name))
+        .and_then(Value::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+meta:
+  storage_dir: /tmp/test
+  derive_certificates: true
+
+certificates:
+  - name: shop
+    domains:
+      - shop.example.com
+    ocsp_stapling: true
+
+envoy:
+  listeners:
+    - name: https
+      filter_chains:
+        - filter_chain_match:
+            server_names: [example.com, www.example.com]
+          transport_socket:
+            name: envoy.transport_sockets.tls
+            typed_config:
+              "@type": type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext
+              common_tls_context:
+                tls_certificate_sds_secret_configs:
+                  - name: site
+        - filter_chain_match:
+            serverNames: [Blog.Example.com]
+          transportSocket:
+            name: envoy.transport_sockets.tls
+            typedConfig:
+              "@type": type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext
+              commonTlsContext:
+                tlsCertificateSdsSecretConfigs:
+                  - name: site
+        - filter_chain_match:
+            server_names: [shop.example.com]
+          transport_socket:
+            name: envoy.transport_sockets.tls
+            typed_config:
+              "@type": type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext
+              common_tls_context:
+                tls_certificate_sds_secret_configs:
+                  - name: shop
+        - filter_chain_match:
+            server_names: [legacy.example.com]
+          transport_socket:
+            name: envoy.transport_sockets.tls
+            typed_config:
+              "@type": type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext
+              common_tls_context:
+                tls_certificate_sds_secret_configs:
+                  - name: legacy
+                    sds_config:
+                      path_config_source: { path: /etc/envoy/legacy.yaml }
+  external_secrets: [legacy]
+"#;
+
+    #[test]
+    fn test_derive_certificates() {
+        let mut config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        derive_certificates(&mut config).unwrap();
+
+        // The explicit entry wins over the derived one and the external secret is skipped
+        assert_eq!(config.certificates.len(), 2);
+        assert!(config.certificates[0].ocsp_stapling);
+        assert_eq!(config.certificates[1].name, "site");
+        assert_eq!(
+            config.certificates[1].domains,
+            vec!["example.com", "www.example.com", "blog.example.com"]
+        );
+
+        let without_server_names = CONFIG.replace("server_names: [shop.example.com]", "{}");
+        let mut config: Config = serde_yaml::from_str(&without_server_names).unwrap();
+        config.certificates.clear();
+        assert!(derive_certificates(&mut config).is_err());
+    }
+}
@@ -3,266 +3,207 @@
 /// The standard pbjson deserializer doesn't handle the expanded form of Any messages
 /// (with @type field and message fields inline). This module provides custom deserialization
 /// that converts the expanded form to the binary form (type_url + encoded bytes).
-use prost::Message;
-use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use xds_api::pb::envoy::config::cluster::v3::Cluster;
 use xds_api::pb::envoy::config::listener::v3::Listener;
-use xds_api::pb::envoy::extensions::filters::http::router::v3::Router;
-use xds_api::pb::envoy::extensions::filters::network::http_connection_manager::v3::HttpConnectionManager;
-use xds_api::pb::envoy::extensions::transport_sockets::tls::v3::SdsSecretConfig;
 
 use crate::error::{Error, Result};
 
-/// Minimal DownstreamTlsContext definition for deserialization
-/// xds-api v0.2.0 doesn't generate this type, so we define the minimal fields needed
-#[derive(Clone, Deserialize, Serialize, prost::Message)]
-struct DownstreamTlsContext {
-    #[prost(message, optional, tag = "1")]
-    #[serde(default)]
-    pub common_tls_context: Option<CommonTlsContext>,
-}
-
-/// Minimal CommonTlsContext definition
-#[derive(Clone, Deserialize, Serialize, prost::Message)]
-struct CommonTlsContext {
-    #[prost(message, repeated, tag = "6")]
-    #[serde(default)]
-    pub tls_certificate_sds_secret_configs: Vec<SdsSecretConfig>,
-}
-
-/// Minimal TcpProxy definition for deserialization
-/// xds-api v0.2.0 doesn't generate this type, so we define the minimal fields needed
-#[derive(Clone, Deserialize, Serialize, prost::Message)]
-struct TcpProxy {
-    #[prost(string, tag = "1")]
-    #[serde(default)]
-    pub stat_prefix: String,
-    #[prost(string, tag = "2")]
-    #[serde(default)]
-    pub cluster: String,
-}
+use super::registry;
 
 /// Deserialize a listener from JSON, handling typed_config fields with @type
 pub fn deserialize_listener(value: &Value) -> Result<Listener> {
-    // First, process any typed_config fields to convert them from expanded to binary form
-    let processed = process_listener_value(value)?;
+    // First, convert typed configs anywhere in the listener from expanded to binary form,
+    // covering filter chains, listener_filters, access_log and default_filter_chain alike
+    let mut listener = value.clone();
+    expand_typed_configs(&mut listener, "listener")?;
 
     // Now deserialize using standard serde
-    serde_json::from_value(processed).map_err(|e| Error::ConfigDeserialize {
+    serde_json::from_value(listener).map_err(|e| Error::ConfigDeserialize {
         item: "Listener",
         source: e,
     })
 }
 
-/// Process a listener JSON value, converting typed_config fields from expanded to binary form
-fn process_listener_value(value: &Value) -> Result<Value> {
-    let mut listener = value.clone();
-
-    // Process filter chains
-    if let Some(filter_chains) = listener
-        .get_mut("filter_chains")
-        .and_then(|v| v.as_array_mut())
-    {
-        for filter_chain in filter_chains {
-            process_filter_chain(filter_chain)?;
-        }
-    }
-
-    Ok(listener)
+/// Deserialize clusters from JSON values, handling typed configs with @type
+///
+/// Typed configs are expanded wherever they appear, e.g. an UpstreamTlsContext transport
+/// socket, `typed_extension_protocol_options` or a `load_balancing_policy`.
+pub fn deserialize_clusters(values: &[Value]) -> Result<Vec<Cluster>> {
+    values
+        .iter()
+        .map(|v| {
+            let mut cluster = v.clone();
+            expand_typed_configs(&mut cluster, "cluster")?;
+            serde_json::from_value(cluster).map_err(|e| Error::ConfigDeserialize {
+                item: "Cluster",
+                source: e,
+            })
+        })
+        .collect()
 }
 
-/// Process a filter chain, converting typed_config in filters
-fn process_filter_chain(filter_chain: &mut Value) -> Result<()> {
-    if let Some(filters) = filter_chain
-        .get_mut("filters")
-        .and_then(|v| v.as_array_mut())
-    {
-        for filter in filters {
-            process_filter(filter)?;
-        }
+/// Recursively convert every expanded Any (an object with @type) to binary form
+///
+/// `filter_metadata` holds opaque Structs, where @type is plain data, so it is left alone.
+fn expand_typed_configs(value: &mut Value, kind: &'static str) -> Result<()> {
+    if value.get("@type").is_some() {
+        *value = registry::encode_any(value, kind)?;
+        return Ok(());
     }
 
-    // Process transport_socket (TLS context) if present
-    if let Some(transport_socket) = filter_chain.get_mut("transport_socket") {
-        process_transport_socket(transport_socket)?;
+    match value {
+        Value::Object(object) => {
+            for (key, field) in object.iter_mut() {
+                if key != "filter_metadata" && key != "filterMetadata" {
+                    expand_typed_configs(field, kind)?;
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                expand_typed_configs(item, kind)?;
+            }
+        }
+        _ => {}
     }
 
     Ok(())
 }
 
-/// Process transport_socket, converting DownstreamTlsContext from expanded to binary form
-fn process_transport_socket(socket: &mut Value) -> Result<()> {
-    if let Some(typed_config) = socket.get("typed_config")
-        && let Some(type_url) = typed_config.get("@type").and_then(|v| v.as_str())
-        && type_url.contains("DownstreamTlsContext")
-    {
-        let type_url_owned = type_url.to_string();
-        let name = socket.get("name").cloned();
-
-        // Encode DownstreamTlsContext to protobuf bytes
-        let encoded = encode_downstream_tls_context(typed_config)?;
-
-        // Replace with binary form
-        *socket = serde_json::json!({
-            "name": name,
-            "typed_config": {
-                "type_url": type_url_owned,
-                "value": encoded
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_cluster_typed_configs() {
+        let cluster = serde_json::json!({
+            "name": "backend",
+            "type": "STRICT_DNS",
+            "transport_socket": {
+                "name": "envoy.transport_sockets.tls",
+                "typed_config": {
+                    "@type": "type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.UpstreamTlsContext",
+                    "sni": "backend.internal",
+                    "common_tls_context": {
+                        "tls_certificate_sds_secret_configs": [{ "name": "client" }]
+                    }
+                }
+            },
+            "typed_extension_protocol_options": {
+                "envoy.extensions.upstreams.http.v3.HttpProtocolOptions": {
+                    "@type": "type.googleapis.com/envoy.extensions.upstreams.http.v3.HttpProtocolOptions",
+                    "explicit_http_config": { "http2_protocol_options": {} }
+                }
+            },
+            "load_balancing_policy": {
+                "policies": [{
+                    "typed_extension_config": {
+                        "name": "envoy.load_balancing_policies.least_request",
+                        "typed_config": {
+                            "@type": "type.googleapis.com/envoy.extensions.load_balancing_policies.least_request.v3.LeastRequest",
+                            "choice_count": 3
+                        }
+                    }
+                }]
+            },
+            "metadata": {
+                "filter_metadata": {
+                    "example": { "fields": { "@type": { "string_value": "opaque" } } }
+                }
             }
         });
-    }
 
-    Ok(())
-}
-
-/// Encode DownstreamTlsContext from expanded JSON form to protobuf bytes
-fn encode_downstream_tls_context(value: &Value) -> Result<Vec<u8>> {
-    // Remove @type field for deserialization
-    let mut v = value.clone();
-    if let Some(obj) = v.as_object_mut() {
-        obj.remove("@type");
+        let clusters = deserialize_clusters(&[cluster]).unwrap();
+        let cluster = &clusters[0];
+        assert!(
+            cluster
+                .transport_socket
+                .as_ref()
+                .is_some_and(|socket| socket.name == "envoy.transport_sockets.tls")
+        );
+        assert_eq!(cluster.typed_extension_protocol_options.len(), 1);
+        assert!(cluster.load_balancing_policy.is_some());
+        assert!(cluster.metadata.is_some());
     }
 
-    // Deserialize to our minimal DownstreamTlsContext struct
-    let tls_context: DownstreamTlsContext =
-        serde_json::from_value(v).map_err(|e| Error::ConfigDeserialize {
-            item: "DownstreamTlsContext",
-            source: e,
-        })?;
-
-    // Encode to protobuf bytes
-    Ok(tls_context.encode_to_vec())
-}
-
-/// Process a filter, converting typed_config from expanded to binary form
-fn process_filter(filter: &mut Value) -> Result<()> {
-    if let Some(typed_config) = filter.get("typed_config")
-        && let Some(type_url) = typed_config.get("@type").and_then(|v| v.as_str())
-    {
-        let type_url_owned = type_url.to_string();
-        let name = filter.get("name").cloned();
-
-        let encoded = match type_url {
-            "type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager" => {
-                encode_http_connection_manager(typed_config)?
-            }
-            "type.googleapis.com/envoy.extensions.filters.network.tcp_proxy.v3.TcpProxy" => {
-                encode_tcp_proxy(typed_config)?
-            }
-            _ => {
-                return Err(Error::ConfigUnsupportedTypeUrl {
-                    kind: "typed_config",
-                    type_url: type_url.to_string(),
-                });
+    #[test]
+    fn test_deserialize_listener_typed_configs() {
+        let tls_socket = serde_json::json!({
+            "name": "envoy.transport_sockets.tls",
+            "typed_config": {
+                "@type": "type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext",
+                "common_tls_context": {
+                    "tls_certificate_sds_secret_configs": [{ "name": "example" }]
+                }
             }
-        };
-
-        // Replace typed_config with binary form
-        // In JSON, oneof fields use the field name directly (typed_config), not wrapped
-        *filter = serde_json::json!({
-            "name": name,
+        });
+        let tcp_proxy = serde_json::json!({
+            "name": "envoy.filters.network.tcp_proxy",
             "typed_config": {
-                "type_url": type_url_owned,
-                "value": encoded
+                "@type": "type.googleapis.com/envoy.extensions.filters.network.tcp_proxy.v3.TcpProxy",
+                "stat_prefix": "passthrough",
+                "cluster": "backend"
             }
         });
-    }
-
-    Ok(())
-}
-
-/// Encode HttpConnectionManager from expanded JSON form to protobuf bytes
-fn encode_http_connection_manager(value: &Value) -> Result<Vec<u8>> {
-    // Remove @type field for deserialization
-    let mut v = value.clone();
-    if let Some(obj) = v.as_object_mut() {
-        obj.remove("@type");
-    }
-
-    // Process nested typed_config in http_filters
-    if let Some(http_filters) = v.get_mut("http_filters").and_then(|v| v.as_array_mut()) {
-        for filter in http_filters {
-            if let Some(typed_config) = filter.get("typed_config")
-                && let Some(type_url) = typed_config.get("@type").and_then(|v| v.as_str())
-            {
-                let type_url_owned = type_url.to_string();
-                let name = filter.get("name").cloned();
-
-                let encoded = match type_url {
-                    "type.googleapis.com/envoy.extensions.filters.http.router.v3.Router" => {
-                        encode_router(typed_config)?
-                    }
-                    _ => {
-                        return Err(Error::ConfigUnsupportedTypeUrl {
-                            kind: "http_filter",
-                            type_url: type_url.to_string(),
-                        });
+        let listener = serde_json::json!({
+            "name": "https",
+            "address": { "socket_address": { "address": "0.0.0.0", "port_value": 443 } },
+            "listener_filters": [
+                {
+                    "name": "envoy.filters.listener.proxy_protocol",
+                    "typed_config": {
+                        "@type": "type.googleapis.com/envoy.extensions.filters.listener.proxy_protocol.v3.ProxyProtocol",
+                        "allow_requests_without_proxy_protocol": true
                     }
-                };
-
-                // HttpFilter uses typed_config directly, not wrapped in config_type
-                *filter = serde_json::json!({
-                    "name": name,
+                },
+                {
+                    "name": "envoy.filters.listener.tls_inspector",
                     "typed_config": {
-                        "type_url": type_url_owned,
-                        "value": encoded
+                        "@type": "type.googleapis.com/envoy.extensions.filters.listener.tls_inspector.v3.TlsInspector",
+                        "enable_ja3_fingerprinting": true
                     }
-                });
+                }
+            ],
+            "access_log": [{
+                "name": "envoy.access_loggers.stdout",
+                "typed_config": {
+                    "@type": "type.googleapis.com/envoy.extensions.access_loggers.stream.v3.StdoutAccessLog"
+                }
+            }],
+            "filter_chains": [{
+                "filter_chain_match": { "server_names": ["example.com"] },
+                "filters": [tcp_proxy],
+                "transport_socket": tls_socket
+            }],
+            "default_filter_chain": {
+                "filters": [tcp_proxy],
+                "transport_socket": tls_socket
             }
-        }
-    }
-
-    // Now deserialize to HttpConnectionManager
-    let hcm: HttpConnectionManager =
-        serde_json::from_value(v).map_err(|e| Error::ConfigDeserialize {
-            item: "HttpConnectionManager",
-            source: e,
-        })?;
-
-    Ok(hcm.encode_to_vec())
-}
-
-/// Encode Router from expanded JSON form to protobuf bytes
-fn encode_router(value: &Value) -> Result<Vec<u8>> {
-    // Router is typically empty, but deserialize it properly
-    let mut v = value.clone();
-    if let Some(obj) = v.as_object_mut() {
-        obj.remove("@type");
-    }
-
-    let router: Router = serde_json::from_value(v).map_err(|e| Error::ConfigDeserialize {
-        item: "Router",
-        source: e,
-    })?;
-
-    Ok(router.encode_to_vec())
-}
+        });
 
-/// Encode TcpProxy from expanded JSON form to protobuf bytes
-fn encode_tcp_proxy(value: &Value) -> Result<Vec<u8>> {
-    let mut v = value.clone();
-    if let Some(obj) = v.as_object_mut() {
-        obj.remove("@type");
+        let listener = deserialize_listener(&listener).unwrap();
+        assert_eq!(listener.listener_filters.len(), 2);
+        assert_eq!(listener.access_log.len(), 1);
+        assert!(
+            listener
+                .default_filter_chain
+                .is_some_and(|chain| chain.transport_socket.is_some())
+        );
+
+        let misspelled = serde_json::json!({
+            "name": "https",
+            "listener_filters": [{
+                "name": "envoy.filters.listener.tls_inspector",
+                "typed_config": {
+                    "@type": "type.googleapis.com/envoy.extensions.filters.listener.tls_inspector.v3.TlsInspector",
+                    "enable_ja3": true
+                }
+            }]
+        });
+        assert!(matches!(
+            deserialize_listener(&misspelled),
+            Err(Error::ConfigTypedConfig { .. })
+        ));
     }
-
-    let tcp_proxy: TcpProxy = serde_json::from_value(v).map_err(|e| Error::ConfigDeserialize {
-        item: "TcpProxy",
-        source: e,
-    })?;
-
-    Ok(tcp_proxy.encode_to_vec())
-}
-
-/// Deserialize clusters from JSON values
-pub fn deserialize_clusters(values: &[Value]) -> Result<Vec<Cluster>> {
-    values
-        .iter()
-        .map(|v| {
-            serde_json::from_value(v.clone()).map_err(|e| Error::ConfigDeserialize {
-                item: "Cluster",
-                source: e,
-            })
-        })
-        .collect()
 }
@@ -0,0 +1,287 @@
+/// `${...}` interpolation of environment variables and files in configuration values
+///
+/// - `${NAME}` is replaced by the environment variable `NAME`.
+/// - `${file:/path}` is replaced by the file's contents, without trailing newlines, e.g. a
+///   systemd credential in `/run/credentials/<unit>/`. Relative paths are resolved against
+///   the directory of the configuration file.
+/// - `${NAME:-default}` and `${file:/path:-default}` fall back to `default` if the variable
+///   is unset or empty, or the file does not exist. Without a default, either is an error.
+/// - `$${` is a literal `${`.
+///
+/// Only string values are interpolated. A value consisting of a single reference that
+/// resolves to an integer or boolean becomes one, so ports and flags can be interpolated.
+///
+/// Resolved variables and file contents are treated as secrets and redacted from
+/// configuration errors, unless they are shorter than `MIN_REDACTED_LEN`.
+use std::path::Path;
+
+use serde_yaml::Value;
+
+use crate::error::{Error, Result};
+
+const REDACTED: &str = "<redacted>";
+
+/// Values shorter than this, such as ports and flags, are left in errors, since replacing
+/// them would garble the message
+const MIN_REDACTED_LEN: usize = 8;
+
+/// Interpolates the files of one configuration load and redacts its errors
+pub struct Interpolator {
+    lookup: fn(&str) -> Option<String>,
+    /// Variables and file contents interpolated so far
+    secrets: Vec<String>,
+}
+
+impl Interpolator {
+    /// Interpolate environment variables from the process environment
+    pub fn from_env() -> Self {
+        Self::new(|name| std::env::var(name).ok())
+    }
+
+    /// Interpolate environment variables from `lookup`
+    pub fn new(lookup: fn(&str) -> Option<String>) -> Self {
+        Self {
+            lookup,
+            secrets: Vec::new(),
+        }
+    }
+
+    /// Interpolate every string in a YAML document read from `directory`
+    pub fn interpolate(&mut self, value: &mut Value, directory: &Path) -> Result<()> {
+        match value {
+            Value::String(string) => {
+                if let Some(interpolated) = self.interpolate_string(string, directory)? {
+                    *value = interpolated;
+                }
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    self.interpolate(item, directory)?;
+                }
+            }
+            Value::Mapping(mapping) => {
+                for (_, item) in mapping.iter_mut() {
+                    self.interpolate(item, directory)?;
+                }
+            }
+            Value::Tagged(tagged) => self.interpolate(&mut tagged.value, directory)?,
+            Value::Null | Value::Bool(_) | Value::Number(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Interpolate a string, or `None` if it contains no references
+    fn interpolate_string(&mut self, string: &str, directory: &Path) -> Result<Option<Value>> {
+        if !string.contains("${") {
+            return Ok(None);
+        }
+
+        let mut output = String::new();
+        let mut references = 0;
+        let mut rest = string;
+        while let Some(start) = rest.find("${") {
+            // "$${" escapes a literal "${"
+            if rest[..start].ends_with('$') {
+                output.push_str(&rest[..start - 1]);
+                output.push_str("${");
+                rest = &rest[start + 2..];
+                continue;
+            }
+
+            output.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| Error::Config(format!("Unterminated '${{' in '{string}'")))?;
+            let resolved = self.resolve(&rest[start + 2..start + end], directory)?;
+            output.push_str(&resolved);
+            references += 1;
+            rest = &rest[start + end + 1..];
+        }
+        output.push_str(rest);
+
+        let whole_reference = references == 1 && string.starts_with("${") && string.ends_with('}');
+        if whole_reference {
+            if let Ok(number) = output.parse::<i64>() {
+                return Ok(Some(Value::Number(number.into())));
+            }
+            if let Ok(boolean) = output.parse::<bool>() {
+                return Ok(Some(Value::Bool(boolean)));
+            }
+        }
+        Ok(Some(Value::String(output)))
+    }
+
+    /// Resolve the contents of a `${...}` reference
+    fn resolve(&mut self, reference: &str, directory: &Path) -> Result<String> {
+        let (source, default) = match reference.split_once(":-") {
+            Some((source, default)) => (source, Some(default)),
+            None => (reference, None),
+        };
+
+        if let Some(path) = source.strip_prefix("file:") {
+            let contents = match std::fs::read_to_string(directory.join(path)) {
+                Ok(contents) => contents.trim_end_matches(['\n', '\r']).to_string(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return default.map(str::to_string).ok_or_else(|| {
+                        Error::Config(format!(
+                            "File '{path}' referenced in the configuration does not exist"
+                        ))
+                    });
+                }
+                Err(e) => {
+                    return Err(Error::Config(format!(
+                        "Failed to read '{path}' for interpolation: {e}"
+                    )));
+                }
+            };
+            self.record_secret(&contents);
+            return Ok(contents);
+        }
+
+        let valid = source
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && source
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(Error::Config(format!(
+                "Invalid environment variable name '{source}' in '${{{reference}}}'"
+            )));
+        }
+
+        match (
+            (self.lookup)(source).filter(|value| !value.is_empty()),
+            default,
+        ) {
+            (Some(value), _) => {
+                self.record_secret(&value);
+                Ok(value)
+            }
+            (None, Some(default)) => Ok(default.to_string()),
+            (None, None) => Err(Error::Config(format!(
+                "Environment variable '{source}' referenced in the configuration is not set"
+            ))),
+        }
+    }
+
+    fn record_secret(&mut self, value: &str) {
+        if value.len() >= MIN_REDACTED_LEN && !self.secrets.iter().any(|secret| secret == value) {
+            self.secrets.push(value.to_string());
+        }
+    }
+
+    /// Replace every interpolated value in a configuration error by a placeholder
+    pub fn redact_error(&self, error: Error) -> Error {
+        let message = match &error {
+            Error::Config(message) => message.clone(),
+            error => error.to_string(),
+        };
+        let redacted = self.redact(&message);
+        if redacted == message {
+            error
+        } else {
+            Error::Config(redacted)
+        }
+    }
+
+    fn redact(&self, message: &str) -> String {
+        let mut secrets: Vec<&String> = self.secrets.iter().collect();
+        // Longer values first, so values containing others are redacted whole
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+        let mut redacted = message.to_string();
+        for secret in secrets {
+            redacted = redacted.replace(secret.as_str(), REDACTED);
+            // serde quotes values in its errors with escaped quotes, backslashes and newlines
+            let escaped = format!("{secret:?}");
+            redacted = redacted.replace(&escaped[1..escaped.len() - 1], REDACTED);
+        }
+        redacted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        let directory =
+            std::env::temp_dir().join(format!("envoy-acme-xds-interpolate-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("hmac"), "c2VjcmV0LWhtYWMta2V5\n").unwrap();
+
+        let mut interpolator = Interpolator::new(|name| match name {
+            "DOMAIN" => Some("staging.example.com".to_string()),
+            "PORT" => Some("8080".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        });
+
+        let yaml = r#"
+domain: "www.${DOMAIN}"
+port: "${PORT}"
+fallback: "${EMPTY:-example.com}"
+hmac: "${file:hmac}"
+literal: "$${NOT_INTERPOLATED}"
+"#;
+        let mut value: Value = serde_yaml::from_str(yaml).unwrap();
+        interpolator.interpolate(&mut value, &directory).unwrap();
+
+        assert_eq!(value["domain"], "www.staging.example.com");
+        assert_eq!(value["port"], 8080);
+        assert_eq!(value["fallback"], "example.com");
+        assert_eq!(value["hmac"], "c2VjcmV0LWhtYWMta2V5");
+        assert_eq!(value["literal"], "${NOT_INTERPOLATED}");
+
+        let mut missing = Value::String("${UNSET}".to_string());
+        assert!(interpolator.interpolate(&mut missing, &directory).is_err());
+        let mut missing_file = Value::String("${file:none}".to_string());
+        assert!(
+            interpolator
+                .interpolate(&mut missing_file, &directory)
+                .is_err()
+        );
+
+        // Values too short to be told apart from the rest of the message are kept
+        let error = interpolator
+            .redact_error(Error::Config(
+                "invalid key c2VjcmV0LWhtYWMta2V5 for www.staging.example.com:8080".to_string(),
+            ))
+            .to_string();
+        assert_eq!(
+            error,
+            "Configuration error: invalid key <redacted> for www.<redacted>:8080"
+        );
+
+        // Each load starts with nothing to redact
+        let fresh = Interpolator::new(|_| None);
+        let error = fresh
+            .redact_error(Error::Config("c2VjcmV0LWhtYWMta2V5".to_string()))
+            .to_string();
+        assert!(error.contains("c2VjcmV0LWhtYWMta2V5"));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_redact_escaped_secret() {
+        let mut interpolator = Interpolator::new(|name| match name {
+            "KEY" => Some("-----BEGIN KEY-----\nc2Vj\"cmV0\\\n-----END KEY-----".to_string()),
+            _ => None,
+        });
+        let mut value: Value = serde_yaml::from_str("port: ${KEY}").unwrap();
+        interpolator
+            .interpolate(&mut value, Path::new("."))
+            .unwrap();
+
+        // A multi-line value is quoted with escapes in the type error
+        let error = serde_yaml::from_value::<u16>(value["port"].clone()).unwrap_err();
+        assert!(error.to_string().contains(r#"c2Vj\"cmV0\\\n"#), "{error}");
+        let error = interpolator.redact_error(error.into()).to_string();
+        assert!(!error.contains("c2Vj"), "{error}");
+        assert!(error.contains("<redacted>"), "{error}");
+    }
+}
@@ -1,21 +1,241 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+use serde::de::DeserializeOwned;
 
 use crate::error::{Error, Result};
 
-use super::types::Config;
+use super::derive::derive_certificates;
+use super::deserialize::{deserialize_clusters, deserialize_listener};
+use super::interpolate::Interpolator;
+use super::types::{
+    CertificateConfig, Config, ConfigFragment, DEFAULT_ISSUER, EnvoyWorkloadConfig, IssuerConfig,
+    StaticCertificateConfig,
+};
+use super::workload::validate_workload;
 
-/// Load configuration from a YAML file
+/// Load configuration from a YAML file, merging in the fragments it includes
+///
+/// Interpolated values are redacted from the returned error.
 pub fn load_config(path: &Path) -> Result<Config> {
-    let content = std::fs::read_to_string(path)?;
-    let config: Config = serde_yaml::from_str(&content)?;
+    let mut interpolator = Interpolator::from_env();
+    load_config_files(path, &mut interpolator).map_err(|e| interpolator.redact_error(e))
+}
+
+fn load_config_files(path: &Path, interpolator: &mut Interpolator) -> Result<Config> {
+    let mut config: Config = read_yaml(path, interpolator)?;
+
+    let mut sources = Sources::default();
+    sources.record_file(
+        path,
+        &config.certificates,
+        &config.static_certificates,
+        &config.envoy,
+    )?;
+
+    for fragment_path in include_files(path, &config.include)? {
+        let fragment: ConfigFragment = read_yaml(&fragment_path, interpolator)?;
+        if fragment.envoy.http3.is_some() {
+            return Err(Error::Config(format!(
+                "{}: envoy.http3 can only be set in the main configuration file",
+                fragment_path.display()
+            )));
+        }
+        sources.record_file(
+            &fragment_path,
+            &fragment.certificates,
+            &fragment.static_certificates,
+            &fragment.envoy,
+        )?;
+
+        config.certificates.extend(fragment.certificates);
+        config
+            .static_certificates
+            .extend(fragment.static_certificates);
+        config.envoy.listeners.extend(fragment.envoy.listeners);
+        config.envoy.clusters.extend(fragment.envoy.clusters);
+        config
+            .envoy
+            .external_clusters
+            .extend(fragment.envoy.external_clusters);
+        config
+            .envoy
+            .external_secrets
+            .extend(fragment.envoy.external_secrets);
+    }
+
+    if config.meta.derive_certificates {
+        derive_certificates(&mut config)?;
+    }
+
     validate_config(&config)?;
+    validate_workload(&config, |kind, index| sources.locate(kind, index))?;
+
+    // Typed config errors may quote interpolated secrets, so they are surfaced here
+    for listener in &config.envoy.listeners {
+        deserialize_listener(listener)?;
+    }
+    deserialize_clusters(&config.envoy.clusters)?;
+
     Ok(config)
 }
 
+fn read_yaml<T: DeserializeOwned>(path: &Path, interpolator: &mut Interpolator) -> Result<T> {
+    let content = std::fs::read_to_string(path).map_err(|source| Error::IoPath {
+        action: "reading",
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let yaml_error = |source| Error::YamlFile {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut value: serde_yaml::Value = serde_yaml::from_str(&content).map_err(yaml_error)?;
+    interpolator
+        .interpolate(&mut value, &config_directory(path))
+        .map_err(|e| Error::Config(format!("{}: {e}", path.display())))?;
+    serde_yaml::from_value(value).map_err(yaml_error)
+}
+
+/// Directory relative paths in a configuration file are resolved against
+pub fn config_directory(config_path: &Path) -> PathBuf {
+    match config_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Resolve `include` entries to patterns matching the fragment files
+///
+/// Directories become patterns for the YAML files directly inside them. A path without
+/// wildcards must exist, so a typo does not silently drop a fragment.
+pub fn include_patterns(config_path: &Path, include: &[String]) -> Result<Vec<Pattern>> {
+    let directory = config_directory(config_path);
+    let mut patterns = Vec::new();
+
+    for entry in include {
+        let path = directory.join(entry);
+        let invalid =
+            |reason: String| Error::Config(format!("Invalid include '{entry}': {reason}"));
+
+        if path.is_dir() {
+            let escaped = Pattern::escape(&path.to_string_lossy());
+            for extension in ["yaml", "yml"] {
+                patterns.push(
+                    Pattern::new(&format!("{escaped}/*.{extension}"))
+                        .map_err(|e| invalid(e.to_string()))?,
+                );
+            }
+            continue;
+        }
+
+        if Pattern::escape(entry) == *entry && !path.exists() {
+            return Err(invalid("no such file or directory".to_string()));
+        }
+        let pattern = if Path::new(entry).is_absolute() {
+            entry.clone()
+        } else {
+            format!("{}/{entry}", Pattern::escape(&directory.to_string_lossy()))
+        };
+        patterns.push(Pattern::new(&pattern).map_err(|e| invalid(e.to_string()))?);
+    }
+
+    Ok(patterns)
+}
+
+/// Fragment files included by a configuration, in include order and sorted per entry
+fn include_files(config_path: &Path, include: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let main_file = std::fs::canonicalize(config_path).ok();
+
+    for pattern in include_patterns(config_path, include)? {
+        let paths = glob::glob(pattern.as_str())
+            .map_err(|e| Error::Config(format!("Invalid include '{pattern}': {e}")))?;
+        let mut matched = Vec::new();
+        for path in paths {
+            let path = path.map_err(|e| Error::IoPath {
+                action: "reading",
+                path: e.path().to_path_buf(),
+                source: e.into(),
+            })?;
+            // A pattern may match the main file, or a file another pattern matched already
+            if path.is_file()
+                && std::fs::canonicalize(&path).ok() != main_file
+                && !files.contains(&path)
+            {
+                matched.push(path);
+            }
+        }
+        matched.sort();
+        files.append(&mut matched);
+    }
+
+    Ok(files)
+}
+
+/// Where certificates, listeners and clusters were defined, to report problems by file
+#[derive(Default)]
+struct Sources {
+    certificates: HashMap<String, PathBuf>,
+    /// File and index within it of each merged listener and cluster
+    listeners: Vec<(PathBuf, usize)>,
+    clusters: Vec<(PathBuf, usize)>,
+}
+
+impl Sources {
+    fn record_file(
+        &mut self,
+        path: &Path,
+        certificates: &[CertificateConfig],
+        static_certificates: &[StaticCertificateConfig],
+        envoy: &EnvoyWorkloadConfig,
+    ) -> Result<()> {
+        // Managed and static certificates share the SDS namespace
+        for name in certificates
+            .iter()
+            .map(|c| &c.name)
+            .chain(static_certificates.iter().map(|c| &c.name))
+        {
+            match self.certificates.entry(name.clone()) {
+                Entry::Occupied(first) => {
+                    return Err(Error::Config(format!(
+                        "Duplicate certificate name '{name}' in {} (first defined in {})",
+                        path.display(),
+                        first.get().display()
+                    )));
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(path.to_path_buf());
+                }
+            }
+        }
+
+        self.listeners
+            .extend((0..envoy.listeners.len()).map(|index| (path.to_path_buf(), index)));
+        self.clusters
+            .extend((0..envoy.clusters.len()).map(|index| (path.to_path_buf(), index)));
+        Ok(())
+    }
+
+    /// Path of a merged listener or cluster in the file it was defined in
+    fn locate(&self, kind: &str, index: usize) -> String {
+        let origins = match kind {
+            "listeners" => &self.listeners,
+            _ => &self.clusters,
+        };
+        let (path, index) = &origins[index];
+        format!("{}: envoy.{kind}[{index}]", path.display())
+    }
+}
+
 /// Validate configuration for correctness
 fn validate_config(config: &Config) -> Result<()> {
     // Validate certificates
-    if config.certificates.is_empty() {
+    if config.certificates.is_empty() && config.static_certificates.is_empty() {
         return Err(Error::Config(
             "At least one certificate configuration is required".to_string(),
         ));
@@ -27,9 +247,15 @@ fn validate_config(config: &Config) -> Result<()> {
                 "Certificate name cannot be empty".to_string(),
             ));
         }
-        if cert.domains.is_empty() {
+        if cert.domains.is_empty() && cert.ip_addresses.is_empty() && !cert.detect_ip_addresses {
+            return Err(Error::Config(format!(
+                "Certificate '{}' must have at least one domain or IP address",
+                cert.name
+            )));
+        }
+        if cert.reuse_key && cert.key_rotation_days == 0 {
             return Err(Error::Config(format!(
-                "Certificate '{}' must have at least one domain",
+                "Certificate '{}' key_rotation_days must be greater than zero",
                 cert.name
             )));
         }
@@ -40,14 +266,35 @@ fn validate_config(config: &Config) -> Result<()> {
                     cert.name
                 )));
             }
+            if domain.parse::<IpAddr>().is_ok() {
+                return Err(Error::Config(format!(
+                    "Certificate '{}' lists IP address '{}' as a domain; use ip_addresses instead",
+                    cert.name, domain
+                )));
+            }
+        }
+    }
+
+    for cert in &config.static_certificates {
+        if cert.name.is_empty() {
+            return Err(Error::Config(
+                "Static certificate name cannot be empty".to_string(),
+            ));
+        }
+        if cert.cert_path.as_os_str().is_empty() || cert.key_path.as_os_str().is_empty() {
+            return Err(Error::Config(format!(
+                "Static certificate '{}' must have a cert_path and key_path",
+                cert.name
+            )));
         }
     }
 
-    // Check for duplicate certificate names
+    // Check for duplicate certificate names, which share the SDS namespace
     let mut names: Vec<&str> = config
         .certificates
         .iter()
         .map(|c| c.name.as_str())
+        .chain(config.static_certificates.iter().map(|c| c.name.as_str()))
         .collect();
     names.sort();
     for window in names.windows(2) {
@@ -59,6 +306,78 @@ fn validate_config(config: &Config) -> Result<()> {
         }
     }
 
+    // Validate issuers
+    for issuer in &config.issuers {
+        if issuer.name.is_empty() {
+            return Err(Error::Config("Issuer name cannot be empty".to_string()));
+        }
+        // The name becomes part of the account's file name
+        let valid = !issuer.name.starts_with('.')
+            && issuer
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid {
+            return Err(Error::Config(format!(
+                "Issuer name '{}' may only contain letters, digits, '_', '.' and '-', and must not start with '.'",
+                issuer.name
+            )));
+        }
+        if issuer.name == DEFAULT_ISSUER {
+            return Err(Error::Config(format!(
+                "Issuer name '{}' is reserved for meta.acme_directory_url",
+                DEFAULT_ISSUER
+            )));
+        }
+        // Preflight accounts are stored as `<issuer>.preflight`
+        if issuer.name.ends_with(".preflight") {
+            return Err(Error::Config(format!(
+                "Issuer name '{}' must not end in '.preflight', which is reserved for preflight accounts",
+                issuer.name
+            )));
+        }
+        if issuer.directory_url.is_empty() {
+            return Err(Error::Config(format!(
+                "Issuer '{}' must have a directory_url",
+                issuer.name
+            )));
+        }
+    }
+
+    for issuer in config.all_issuers() {
+        if let Some(preflight_url) = &issuer.preflight_directory_url
+            && (preflight_url.is_empty() || *preflight_url == issuer.directory_url)
+        {
+            return Err(Error::Config(format!(
+                "Issuer '{}' preflight directory must be a different, non-empty URL",
+                issuer.name
+            )));
+        }
+    }
+
+    let mut issuer_names: Vec<&str> = config.issuers.iter().map(|i| i.name.as_str()).collect();
+    issuer_names.sort();
+    for window in issuer_names.windows(2) {
+        if window[0] == window[1] {
+            return Err(Error::Config(format!(
+                "Duplicate issuer name: '{}'",
+                window[0]
+            )));
+        }
+    }
+
+    let all_issuers = config.all_issuers();
+    for cert in &config.certificates {
+        let Some(issuer) = all_issuers.iter().find(|i| i.name == cert.issuer_name()) else {
+            return Err(Error::Config(format!(
+                "Certificate '{}' references unknown issuer '{}'",
+                cert.name,
+                cert.issuer_name()
+            )));
+        };
+        validate_csr_options(cert, issuer)?;
+    }
+
     // Validate meta config
     if let Some(socket_path) = &config.meta.socket_path
         && socket_path.as_os_str().is_empty()
@@ -66,6 +385,33 @@ fn validate_config(config: &Config) -> Result<()> {
         return Err(Error::Config("Socket path cannot be empty".to_string()));
     }
 
+    let rate_limit = &config.meta.order_rate_limit;
+    if rate_limit.burst == 0 || rate_limit.per_hour == 0 {
+        return Err(Error::Config(
+            "order_rate_limit burst and per_hour must be greater than zero".to_string(),
+        ));
+    }
+
+    if config.meta.max_concurrent_orders == 0 {
+        return Err(Error::Config(
+            "max_concurrent_orders must be greater than zero".to_string(),
+        ));
+    }
+
+    if config.meta.challenge_propagation_timeout_secs == 0 {
+        return Err(Error::Config(
+            "challenge_propagation_timeout_secs must be greater than zero".to_string(),
+        ));
+    }
+
+    if let Some(address) = &config.meta.challenge_self_check_address
+        && address.is_empty()
+    {
+        return Err(Error::Config(
+            "challenge_self_check_address cannot be empty".to_string(),
+        ));
+    }
+
     if config.meta.storage_dir.as_os_str().is_empty() {
         return Err(Error::Config(
             "Storage directory cannot be empty".to_string(),
@@ -75,6 +421,82 @@ fn validate_config(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Validate a certificate's CSR options against its issuer
+fn validate_csr_options(cert: &CertificateConfig, issuer: &IssuerConfig) -> Result<()> {
+    let csr = &cert.csr;
+
+    if csr.must_staple {
+        if !issuer.must_staple_supported() {
+            return Err(Error::Config(format!(
+                "Certificate '{}' requests Must-Staple, which issuer '{}' does not support",
+                cert.name, issuer.name
+            )));
+        }
+        if !cert.ocsp_stapling {
+            return Err(Error::Config(format!(
+                "Certificate '{}' requests Must-Staple and therefore requires ocsp_stapling",
+                cert.name
+            )));
+        }
+    }
+
+    if let Some(common_name) = &csr.common_name {
+        if csr.omit_common_name {
+            return Err(Error::Config(format!(
+                "Certificate '{}' sets both common_name and omit_common_name",
+                cert.name
+            )));
+        }
+        let listed = cert
+            .domains
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(common_name))
+            || cert
+                .ip_addresses
+                .iter()
+                .any(|ip| ip.to_string() == *common_name);
+        if !listed {
+            return Err(Error::Config(format!(
+                "Certificate '{}' common_name '{}' is not one of its domains or IP addresses",
+                cert.name, common_name
+            )));
+        }
+    }
+
+    // X.520 limits the common name to 64 characters
+    let common_name = csr
+        .common_name
+        .as_ref()
+        .or(cert.domains.first())
+        .filter(|_| !csr.omit_common_name);
+    if let Some(common_name) = common_name
+        && common_name.len() > 64
+    {
+        return Err(Error::Config(format!(
+            "Certificate '{}' common name '{}' exceeds 64 characters; set csr.common_name or csr.omit_common_name",
+            cert.name, common_name
+        )));
+    }
+
+    if !csr.subject.is_empty() && !issuer.supports_subject_attributes {
+        return Err(Error::Config(format!(
+            "Certificate '{}' sets subject attributes, which issuer '{}' does not support",
+            cert.name, issuer.name
+        )));
+    }
+
+    if let Some(country) = &csr.subject.country
+        && (country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()))
+    {
+        return Err(Error::Config(format!(
+            "Certificate '{}' country '{}' must be a two-letter ISO 3166 code",
+            cert.name, country
+        )));
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,4 +575,225 @@ certificates:
         let config: Config = serde_yaml::from_str(yaml).unwrap();
         assert!(validate_config(&config).is_err());
     }
+
+    #[test]
+    fn test_certificate_issuer_override() {
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+
+issuers:
+  - name: internal
+    directory_url: https://ca.internal/acme/directory
+
+certificates:
+  - name: public
+    domains:
+      - example.com
+  - name: private
+    issuer: internal
+    domains:
+      - service.internal
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(validate_config(&config).is_ok());
+        assert_eq!(config.certificates[0].issuer_name(), DEFAULT_ISSUER);
+        assert_eq!(config.certificates[1].issuer_name(), "internal");
+        assert_eq!(config.all_issuers().len(), 2);
+    }
+
+    #[test]
+    fn test_issuer_names() {
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+
+issuers:
+  - name: NAME
+    directory_url: https://ca.internal/acme/directory
+
+certificates:
+  - name: private
+    issuer: NAME
+    domains:
+      - service.internal
+"#;
+        for name in ["internal", "ca-2.internal_v2"] {
+            let config: Config = serde_yaml::from_str(&yaml.replace("NAME", name)).unwrap();
+            assert!(validate_config(&config).is_ok(), "{name}");
+        }
+        for name in ["../escape", "a/b", ".hidden", "ca internal"] {
+            let config: Config = serde_yaml::from_str(&yaml.replace("NAME", name)).unwrap();
+            assert!(validate_config(&config).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_unknown_certificate_issuer() {
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+
+certificates:
+  - name: private
+    issuer: internal
+    domains:
+      - service.internal
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_preflight_directory() {
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+  acme_preflight_directory_url: https://acme-staging-v02.api.letsencrypt.org/directory
+
+certificates:
+  - name: example
+    domains:
+      - example.com
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(validate_config(&config).is_ok());
+        assert!(config.all_issuers()[0].preflight_directory_url.is_some());
+
+        let same = yaml.replace("acme-staging-v02", "acme-v02");
+        let config: Config = serde_yaml::from_str(&same).unwrap();
+        assert!(validate_config(&config).is_err());
+
+        // An issuer must not take the name of another issuer's preflight account
+        let clash = format!(
+            "{yaml}\nissuers:\n  - name: default.preflight\n    directory_url: https://ca.internal/directory\n"
+        );
+        let config: Config = serde_yaml::from_str(&clash).unwrap();
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_ip_address_identifiers() {
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+
+certificates:
+  - name: internal
+    ip_addresses:
+      - 10.0.0.5
+      - "2001:db8::5"
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(validate_config(&config).is_ok());
+        assert_eq!(config.certificates[0].ip_addresses.len(), 2);
+
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+
+certificates:
+  - name: internal
+    domains:
+      - 10.0.0.5
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_csr_options_validated_against_issuer() {
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+  acme_directory_url: https://acme-v02.api.letsencrypt.org/directory
+
+issuers:
+  - name: internal
+    directory_url: https://ca.internal/acme/directory
+    supports_subject_attributes: true
+
+certificates:
+  - name: internal
+    issuer: internal
+    domains:
+      - service.internal
+    ocsp_stapling: true
+    csr:
+      must_staple: true
+      common_name: service.internal
+      subject:
+        organization: Example Corp
+        country: DE
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(validate_config(&config).is_ok());
+
+        // Let's Encrypt supports neither Must-Staple nor subject attributes
+        let public = yaml.replace("    issuer: internal\n", "");
+        let config: Config = serde_yaml::from_str(&public).unwrap();
+        assert!(validate_config(&config).is_err());
+
+        let foreign_cn = yaml.replace(
+            "common_name: service.internal",
+            "common_name: other.internal",
+        );
+        let config: Config = serde_yaml::from_str(&foreign_cn).unwrap();
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_include_fragments() {
+        let directory = std::env::temp_dir().join(format!("envoy-acme-xds-{}", std::process::id()));
+        let fragments = directory.join("conf.d");
+        std::fs::create_dir_all(&fragments).unwrap();
+        let main = directory.join("config.yaml");
+        std::fs::write(
+            &main,
+            r#"
+include:
+  - conf.d
+meta:
+  storage_dir: /tmp/test
+certificates:
+  - name: main
+    domains:
+      - example.com
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            fragments.join("a.yaml"),
+            r#"
+certificates:
+  - name: site-a
+    domains:
+      - a.example.com
+envoy:
+  clusters:
+    - name: site_a
+"#,
+        )
+        .unwrap();
+
+        let config = load_config(&main).unwrap();
+        assert_eq!(config.certificates.len(), 2);
+        assert_eq!(config.envoy.clusters.len(), 1);
+
+        std::fs::write(
+            fragments.join("b.yml"),
+            "envoy:\n  clusters:\n    - name: site_a\n",
+        )
+        .unwrap();
+        let error = load_config(&main).unwrap_err().to_string();
+        assert!(
+            error.contains("b.yml") && error.contains("a.yaml"),
+            "{error}"
+        );
+
+        std::fs::write(fragments.join("b.yml"), "meta: {}\n").unwrap();
+        assert!(load_config(&main).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
 }
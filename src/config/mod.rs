@@ -1,7 +1,14 @@
+mod derive;
 mod deserialize;
+mod interpolate;
 mod loader;
+mod registry;
 mod types;
+mod workload;
 
 pub use deserialize::{deserialize_clusters, deserialize_listener};
-pub use loader::load_config;
-pub use types::{CertificateConfig, Config, EnvoyWorkloadConfig};
+pub use loader::{config_directory, include_patterns, load_config};
+pub use types::{
+    CertificateConfig, Config, CsrOptions, DEFAULT_ISSUER, EnvoyWorkloadConfig, Http3Config,
+    IssuerConfig, KeyType, OrderRateLimit, StaticCertificateConfig,
+};
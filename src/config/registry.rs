@@ -0,0 +1,163 @@
+/// Descriptor-based encoding of `Any` messages for any Envoy extension type
+///
+/// xds-api only generates a subset of the Envoy API, so typed configs are transcoded with
+/// `prost-reflect` against the bundled descriptors instead of generated structs. Nested
+/// `Any` messages and well-known types (durations, wrappers, structs) are handled at any
+/// depth.
+use std::sync::LazyLock;
+
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// Envoy API descriptors, compiled from the protos by `tools/envoy-descriptors`
+static DESCRIPTORS: &[u8] = include_bytes!("envoy-descriptors.bin");
+
+static POOL: LazyLock<DescriptorPool> = LazyLock::new(|| {
+    DescriptorPool::decode(DESCRIPTORS).expect("bundled Envoy descriptors are valid")
+});
+
+/// Encode an `Any` in expanded form (`@type` plus the message fields) to its binary form
+///
+/// `kind` names the field being encoded for error messages. Unknown fields are rejected,
+/// so a typo fails the configuration instead of being silently dropped.
+pub fn encode_any(value: &Value, kind: &'static str) -> Result<Value> {
+    let mut fields = value.clone();
+    let type_url = fields
+        .as_object_mut()
+        .and_then(|object| object.remove("@type"))
+        .and_then(|type_url| type_url.as_str().map(str::to_string))
+        .ok_or_else(|| Error::Config(format!("{kind} is missing its @type")))?;
+
+    let type_name = type_url.rsplit('/').next().unwrap_or_default();
+    let descriptor =
+        POOL.get_message_by_name(type_name)
+            .ok_or_else(|| Error::ConfigUnsupportedTypeUrl {
+                kind,
+                type_url: type_url.clone(),
+            })?;
+
+    let message = DynamicMessage::deserialize(descriptor, fields).map_err(|source| {
+        Error::ConfigTypedConfig {
+            type_url: type_url.clone(),
+            source,
+        }
+    })?;
+
+    Ok(serde_json::json!({
+        "type_url": type_url,
+        "value": message.encode_to_vec(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use xds_api::pb::envoy::extensions::filters::network::http_connection_manager::v3::HttpConnectionManager;
+
+    use super::*;
+
+    #[test]
+    fn test_encode_nested_any() {
+        let value = serde_json::json!({
+            "@type": "type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager",
+            "stat_prefix": "ingress",
+            "stream_idle_timeout": "300s",
+            "http_filters": [
+                {
+                    "name": "envoy.filters.http.cors",
+                    "typed_config": {
+                        "@type": "type.googleapis.com/envoy.extensions.filters.http.cors.v3.Cors"
+                    }
+                },
+                {
+                    "name": "envoy.filters.http.rbac",
+                    "typed_config": {
+                        "@type": "type.googleapis.com/envoy.extensions.filters.http.rbac.v3.RBAC",
+                        "rules": {
+                            "action": "DENY",
+                            "policies": {
+                                "block-admin": {
+                                    "permissions": [{ "url_path": { "path": { "prefix": "/admin" } } }],
+                                    "principals": [{ "any": true }]
+                                }
+                            }
+                        }
+                    }
+                },
+                {
+                    "name": "envoy.filters.http.router",
+                    "typed_config": {
+                        "@type": "type.googleapis.com/envoy.extensions.filters.http.router.v3.Router"
+                    }
+                }
+            ]
+        });
+
+        let encoded = encode_any(&value, "typed_config").unwrap();
+        let bytes: Vec<u8> = serde_json::from_value(encoded["value"].clone()).unwrap();
+        let hcm = HttpConnectionManager::decode(bytes.as_slice()).unwrap();
+        assert_eq!(hcm.stat_prefix, "ingress");
+        assert_eq!(hcm.stream_idle_timeout.unwrap().seconds, 300);
+        assert_eq!(hcm.http_filters.len(), 3);
+        assert_eq!(hcm.http_filters[1].name, "envoy.filters.http.rbac");
+
+        let unknown_type = serde_json::json!({ "@type": "type.googleapis.com/example.Missing" });
+        assert!(matches!(
+            encode_any(&unknown_type, "typed_config"),
+            Err(Error::ConfigUnsupportedTypeUrl { .. })
+        ));
+
+        let unknown_field = serde_json::json!({
+            "@type": "type.googleapis.com/envoy.extensions.filters.http.router.v3.Router",
+            "no_such_field": true
+        });
+        assert!(matches!(
+            encode_any(&unknown_field, "typed_config"),
+            Err(Error::ConfigTypedConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn test_encode_downstream_tls_context() {
+        let type_url =
+            "type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext";
+        let value = serde_json::json!({
+            "@type": type_url,
+            "require_client_certificate": true,
+            "common_tls_context": {
+                "alpn_protocols": ["h2", "http/1.1"],
+                "tls_params": { "tls_minimum_protocol_version": "TLSv1_2" },
+                "tls_certificate_sds_secret_configs": [{ "name": "example" }],
+                "validation_context": { "trusted_ca": { "filename": "/etc/ssl/ca.pem" } }
+            }
+        });
+
+        let encoded = encode_any(&value, "transport_socket").unwrap();
+        let bytes: Vec<u8> = serde_json::from_value(encoded["value"].clone()).unwrap();
+        let descriptor = POOL
+            .get_message_by_name(type_url.rsplit('/').next().unwrap())
+            .unwrap();
+        let decoded = DynamicMessage::decode(descriptor, bytes.as_slice()).unwrap();
+        let decoded = serde_json::to_value(&decoded).unwrap();
+        assert_eq!(decoded["requireClientCertificate"], true);
+        assert_eq!(
+            decoded["commonTlsContext"]["alpnProtocols"],
+            serde_json::json!(["h2", "http/1.1"])
+        );
+        assert_eq!(
+            decoded["commonTlsContext"]["tlsParams"]["tlsMinimumProtocolVersion"],
+            "TLSv1_2"
+        );
+
+        let misspelled = serde_json::json!({
+            "@type": type_url,
+            "common_tls_context": { "alpn_protocol": ["h2"] }
+        });
+        assert!(matches!(
+            encode_any(&misspelled, "transport_socket"),
+            Err(Error::ConfigTypedConfig { .. })
+        ));
+    }
+}
@@ -1,17 +1,42 @@
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
+/// Name of the issuer built from `meta.acme_directory_url`
+pub const DEFAULT_ISSUER: &str = "default";
+
 /// Root configuration structure
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    /// Files or glob patterns, relative to this file, whose fragments are merged in.
+    /// A directory includes every `.yaml` and `.yml` file in it.
+    #[serde(default)]
+    pub include: Vec<String>,
     pub meta: MetaConfig,
+    #[serde(default)]
+    pub issuers: Vec<IssuerConfig>,
+    #[serde(default)]
     pub certificates: Vec<CertificateConfig>,
     #[serde(default)]
+    pub static_certificates: Vec<StaticCertificateConfig>,
+    #[serde(default)]
     pub envoy: EnvoyWorkloadConfig,
 }
 
-/// Metadata configuration
+/// Configuration fragment merged into the root configuration through `include`
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFragment {
+    #[serde(default)]
+    pub certificates: Vec<CertificateConfig>,
+    #[serde(default)]
+    pub static_certificates: Vec<StaticCertificateConfig>,
+    #[serde(default)]
+    pub envoy: EnvoyWorkloadConfig,
+}
+
+/// Metadata configuration
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct MetaConfig {
     /// Directory for storing account data, keys, and certificates
     pub storage_dir: PathBuf,
@@ -20,6 +45,11 @@ pub struct MetaConfig {
     #[serde(default = "default_acme_directory")]
     pub acme_directory_url: String,
 
+    /// Staging directory to validate new or changed certificates against before they are
+    /// ordered from `acme_directory_url`
+    #[serde(default)]
+    pub acme_preflight_directory_url: Option<String>,
+
     /// Unix socket path for xDS server (optional when using systemd socket activation)
     #[serde(default)]
     pub socket_path: Option<PathBuf>,
@@ -33,6 +63,66 @@ pub struct MetaConfig {
     /// Set this to match your HTTP listener port
     #[serde(default = "default_acme_challenge_port")]
     pub acme_challenge_port: u16,
+
+    /// Seconds to wait for Envoy to acknowledge challenge routes before giving up on an order
+    #[serde(default = "default_challenge_propagation_timeout_secs")]
+    pub challenge_propagation_timeout_secs: u64,
+
+    /// Address of the Envoy challenge listener (e.g., "127.0.0.1:80") used to fetch each
+    /// challenge response before asking the CA to validate it
+    #[serde(default)]
+    pub challenge_self_check_address: Option<String>,
+
+    /// Address for a built-in HTTP server answering HTTP-01 challenges (e.g., "0.0.0.0:80"),
+    /// allowing issuance before Envoy connects
+    #[serde(default)]
+    pub challenge_responder_address: Option<SocketAddr>,
+
+    /// Token bucket limiting new ACME orders per account
+    #[serde(default)]
+    pub order_rate_limit: OrderRateLimit,
+
+    /// Maximum number of certificate orders processed concurrently
+    #[serde(default = "default_max_concurrent_orders")]
+    pub max_concurrent_orders: usize,
+
+    /// Reload the configuration when its file changes, in addition to on SIGHUP
+    #[serde(default)]
+    pub watch_config: bool,
+
+    /// Manage a certificate for every SDS secret that listeners reference without a
+    /// `certificates` or `static_certificates` entry, covering the chains' server names
+    #[serde(default)]
+    pub derive_certificates: bool,
+}
+
+/// Token bucket settings for new ACME orders, applied to each account separately
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OrderRateLimit {
+    /// Number of orders that can be created back to back
+    #[serde(default = "default_order_burst")]
+    pub burst: u32,
+
+    /// Sustained number of new orders per hour
+    #[serde(default = "default_orders_per_hour")]
+    pub per_hour: u32,
+}
+
+impl Default for OrderRateLimit {
+    fn default() -> Self {
+        Self {
+            burst: default_order_burst(),
+            per_hour: default_orders_per_hour(),
+        }
+    }
+}
+
+fn default_order_burst() -> u32 {
+    10
+}
+
+fn default_orders_per_hour() -> u32 {
+    50
 }
 
 fn default_socket_permissions() -> u32 {
@@ -43,18 +133,195 @@ fn default_acme_challenge_port() -> u16 {
     80
 }
 
+fn default_challenge_propagation_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_concurrent_orders() -> usize {
+    4
+}
+
+fn default_key_rotation_days() -> u32 {
+    365
+}
+
 fn default_acme_directory() -> String {
     "https://acme-v02.api.letsencrypt.org/directory".to_string()
 }
 
+impl Config {
+    /// All issuers, including the default issuer built from `meta.acme_directory_url`
+    pub fn all_issuers(&self) -> Vec<IssuerConfig> {
+        let mut issuers = vec![IssuerConfig {
+            name: DEFAULT_ISSUER.to_string(),
+            directory_url: self.meta.acme_directory_url.clone(),
+            preflight_directory_url: self.meta.acme_preflight_directory_url.clone(),
+            supports_must_staple: None,
+            supports_subject_attributes: false,
+        }];
+        issuers.extend(self.issuers.iter().cloned());
+        issuers
+    }
+}
+
+/// Issuer configuration - a named ACME directory with its own account
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct IssuerConfig {
+    /// Name referenced by `certificates[].issuer`
+    pub name: String,
+
+    /// ACME directory URL for this issuer
+    pub directory_url: String,
+
+    /// Staging directory to validate new or changed certificates against before they are
+    /// ordered from `directory_url`
+    #[serde(default)]
+    pub preflight_directory_url: Option<String>,
+
+    /// Whether the CA issues certificates with the TLS Feature (Must-Staple) extension
+    /// (defaults to true, except for Let's Encrypt which no longer does)
+    #[serde(default)]
+    pub supports_must_staple: Option<bool>,
+
+    /// Whether the CA honors subject attributes other than the common name
+    #[serde(default)]
+    pub supports_subject_attributes: bool,
+}
+
+impl IssuerConfig {
+    /// Whether certificates from this issuer may request Must-Staple
+    pub fn must_staple_supported(&self) -> bool {
+        self.supports_must_staple
+            .unwrap_or_else(|| !self.directory_url.contains(".api.letsencrypt.org/"))
+    }
+}
+
 /// Certificate configuration - defines a certificate to be issued
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct CertificateConfig {
     /// Name used for SDS secret reference and storage directory
     pub name: String,
 
     /// List of domains to include on the certificate
+    #[serde(default)]
     pub domains: Vec<String>,
+
+    /// IP addresses to include on the certificate (RFC 8738)
+    #[serde(default)]
+    pub ip_addresses: Vec<IpAddr>,
+
+    /// Also include the addresses this host uses for outbound IPv4 and IPv6 traffic
+    #[serde(default)]
+    pub detect_ip_addresses: bool,
+
+    /// Issuer to obtain the certificate from (defaults to `meta.acme_directory_url`)
+    #[serde(default)]
+    pub issuer: Option<String>,
+
+    /// Fetch OCSP responses from the certificate's responder and staple them via SDS
+    #[serde(default)]
+    pub ocsp_stapling: bool,
+
+    /// Type of private key generated for the certificate
+    #[serde(default)]
+    pub key_type: KeyType,
+
+    /// Extensions and subject fields requested in the CSR
+    #[serde(default)]
+    pub csr: CsrOptions,
+
+    /// Build renewal CSRs from the stored private key instead of generating a new one
+    #[serde(default)]
+    pub reuse_key: bool,
+
+    /// Days after which a reused key is replaced, triggering a renewal if needed
+    #[serde(default = "default_key_rotation_days")]
+    pub key_rotation_days: u32,
+}
+
+/// Static certificate configuration - a certificate and key supplied as PEM files
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StaticCertificateConfig {
+    /// Name used for SDS secret reference
+    pub name: String,
+
+    /// PEM file containing the certificate chain, leaf first
+    pub cert_path: PathBuf,
+
+    /// PEM file containing the private key
+    pub key_path: PathBuf,
+}
+
+/// CSR options for a certificate
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct CsrOptions {
+    /// Request the TLS Feature (OCSP Must-Staple) extension
+    #[serde(default)]
+    pub must_staple: bool,
+
+    /// Common name, one of the certificate's domains or IP addresses (defaults to the first domain)
+    #[serde(default)]
+    pub common_name: Option<String>,
+
+    /// Leave the common name out of the subject
+    #[serde(default)]
+    pub omit_common_name: bool,
+
+    /// Additional subject attributes, for CAs that honor them
+    #[serde(default)]
+    pub subject: SubjectAttributes,
+}
+
+/// Subject attributes besides the common name
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SubjectAttributes {
+    #[serde(default)]
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub organizational_unit: Option<String>,
+    /// Two-letter ISO 3166 country code
+    #[serde(default)]
+    pub country: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub locality: Option<String>,
+}
+
+impl SubjectAttributes {
+    /// Whether no attributes are set
+    pub fn is_empty(&self) -> bool {
+        self.organization.is_none()
+            && self.organizational_unit.is_none()
+            && self.country.is_none()
+            && self.state.is_none()
+            && self.locality.is_none()
+    }
+}
+
+/// Private key algorithm for issued certificates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    #[default]
+    EcdsaP256,
+    EcdsaP384,
+}
+
+impl std::fmt::Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyType::EcdsaP256 => f.write_str("ecdsa-p256"),
+            KeyType::EcdsaP384 => f.write_str("ecdsa-p384"),
+        }
+    }
+}
+
+impl CertificateConfig {
+    /// Name of the issuer this certificate is obtained from
+    pub fn issuer_name(&self) -> &str {
+        self.issuer.as_deref().unwrap_or(DEFAULT_ISSUER)
+    }
 }
 
 /// Workload Envoy configuration - mirrors static_resources structure
@@ -65,4 +332,33 @@ pub struct EnvoyWorkloadConfig {
 
     #[serde(default)]
     pub clusters: Vec<serde_json::Value>,
+
+    /// Clusters defined outside this configuration (e.g., in the Envoy bootstrap) that
+    /// routes may refer to
+    #[serde(default)]
+    pub external_clusters: Vec<String>,
+
+    /// SDS secrets provided outside this server (e.g., by another SDS server or from files)
+    /// that listeners and clusters may refer to
+    #[serde(default)]
+    pub external_secrets: Vec<String>,
+
+    /// Generate HTTP/3 (QUIC) twins of TLS listeners
+    #[serde(default)]
+    pub http3: Option<Http3Config>,
+}
+
+/// HTTP/3 listener generation
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Http3Config {
+    /// Names of TLS listeners to serve over QUIC as well, on the same port over UDP
+    pub listeners: Vec<String>,
+
+    /// `ma` parameter of the `alt-svc` header advertising HTTP/3, in seconds
+    #[serde(default = "default_alt_svc_max_age_secs")]
+    pub alt_svc_max_age_secs: u32,
+}
+
+fn default_alt_svc_max_age_secs() -> u32 {
+    86400
 }
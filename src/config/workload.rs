@@ -0,0 +1,467 @@
+/// Cross-reference validation of the Envoy workload configuration
+///
+/// Works on the configuration as written, so every error can name its path in the YAML.
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use serde_json::Value;
+
+use crate::envoy::ACME_LISTENER_NAME;
+use crate::error::{Error, Result};
+
+use super::types::Config;
+
+const HTTP_CONNECTION_MANAGER_TYPE_URL: &str = "type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager";
+const TCP_PROXY_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.filters.network.tcp_proxy.v3.TcpProxy";
+const DOWNSTREAM_TLS_CONTEXT_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext";
+const UPSTREAM_TLS_CONTEXT_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.UpstreamTlsContext";
+const QUIC_DOWNSTREAM_TRANSPORT_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.transport_sockets.quic.v3.QuicDownstreamTransport";
+
+/// A value in the configuration together with its path
+#[derive(Clone)]
+struct Node<'a> {
+    value: &'a Value,
+    path: String,
+}
+
+impl<'a> Node<'a> {
+    /// Field by its proto name or, as Envoy also accepts, its JSON (camelCase) name
+    fn field(&self, name: &str, json_name: &str) -> Option<Node<'a>> {
+        [name, json_name].into_iter().find_map(|key| {
+            self.value.get(key).map(|value| Node {
+                value,
+                path: format!("{}.{key}", self.path),
+            })
+        })
+    }
+
+    fn items(&self) -> Vec<Node<'a>> {
+        self.value
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| Node {
+                        value,
+                        path: format!("{}[{index}]", self.path),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn field_items(&self, name: &str, json_name: &str) -> Vec<Node<'a>> {
+        self.field(name, json_name)
+            .map(|field| field.items())
+            .unwrap_or_default()
+    }
+
+    fn as_str(&self) -> Option<&'a str> {
+        self.value.as_str()
+    }
+
+    /// Typed config of the given type, with `@type` as written in expanded form
+    fn typed_config(&self, type_url: &str) -> Option<Node<'a>> {
+        self.field("typed_config", "typedConfig")
+            .filter(|config| config.value.get("@type").and_then(Value::as_str) == Some(type_url))
+    }
+}
+
+/// Check that the listeners and clusters fit together and with the certificates
+///
+/// `locate` gives the location of a listener or cluster by its kind (`listeners` or
+/// `clusters`) and index. All problems are reported at once.
+pub fn validate_workload(config: &Config, locate: impl Fn(&str, usize) -> String) -> Result<()> {
+    let listeners = nodes(&config.envoy.listeners, "listeners", &locate);
+    let clusters = nodes(&config.envoy.clusters, "clusters", &locate);
+
+    let mut errors = Vec::new();
+
+    check_duplicate_names("listener", &listeners, &mut errors);
+    check_duplicate_names("cluster", &clusters, &mut errors);
+
+    for listener in &listeners {
+        if let Some(name) = listener.field("name", "name")
+            && name.as_str() == Some(ACME_LISTENER_NAME)
+        {
+            errors.push(format!(
+                "{}: '{ACME_LISTENER_NAME}' is reserved for the generated ACME challenge listener",
+                name.path
+            ));
+        }
+    }
+
+    let mut bound: HashMap<(String, u64, String), String> = HashMap::new();
+    for listener in &listeners {
+        let Some(socket_address) = listener
+            .field("address", "address")
+            .and_then(|address| address.field("socket_address", "socketAddress"))
+        else {
+            continue;
+        };
+        let address = socket_address
+            .field("address", "address")
+            .and_then(|address| address.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let Some(port) = socket_address
+            .field("port_value", "portValue")
+            .and_then(|port| port.value.as_u64())
+        else {
+            continue;
+        };
+        let protocol = socket_address
+            .field("protocol", "protocol")
+            .and_then(|protocol| protocol.as_str())
+            .unwrap_or("TCP")
+            .to_uppercase();
+
+        match bound.get(&(address.clone(), port, protocol.clone())) {
+            Some(first) => errors.push(format!(
+                "{}: {protocol} {address}:{port} is already bound by {first}",
+                socket_address.path
+            )),
+            None => {
+                bound.insert((address, port, protocol), socket_address.path.clone());
+            }
+        }
+    }
+
+    let cluster_names: HashSet<&str> = clusters
+        .iter()
+        .filter_map(|cluster| cluster.field("name", "name")?.as_str())
+        .chain(config.envoy.external_clusters.iter().map(String::as_str))
+        .collect();
+    let secret_names: HashSet<&str> = config
+        .certificates
+        .iter()
+        .map(|c| c.name.as_str())
+        .chain(config.static_certificates.iter().map(|c| c.name.as_str()))
+        .chain(config.envoy.external_secrets.iter().map(String::as_str))
+        .collect();
+
+    for listener in &listeners {
+        let chains = listener
+            .field_items("filter_chains", "filterChains")
+            .into_iter()
+            .chain(listener.field("default_filter_chain", "defaultFilterChain"));
+        for chain in chains {
+            let secrets = downstream_secrets(&chain);
+            for secret in &secrets {
+                check_secret(secret, &secret_names, &mut errors);
+            }
+
+            // Domains of the chain's certificates, unless it has none or a static or external one
+            let certificate_domains: Option<Vec<String>> = (!secrets.is_empty())
+                .then(|| {
+                    secrets
+                        .iter()
+                        .map(|secret| {
+                            let name = secret.as_str()?;
+                            let certificate =
+                                config.certificates.iter().find(|c| c.name == name)?;
+                            Some(
+                                certificate
+                                    .domains
+                                    .iter()
+                                    .map(|domain| domain.to_lowercase())
+                                    .chain(certificate.ip_addresses.iter().map(IpAddr::to_string))
+                                    .collect::<Vec<_>>(),
+                            )
+                        })
+                        .collect::<Option<Vec<_>>>()
+                })
+                .flatten()
+                .map(|domains| domains.concat());
+
+            for filter in chain.field_items("filters", "filters") {
+                if let Some(hcm) = filter.typed_config(HTTP_CONNECTION_MANAGER_TYPE_URL) {
+                    check_route_config(
+                        &hcm,
+                        certificate_domains.as_deref(),
+                        &cluster_names,
+                        &mut errors,
+                    );
+                }
+                if let Some(tcp_proxy) = filter.typed_config(TCP_PROXY_TYPE_URL) {
+                    check_cluster_references(&tcp_proxy, &cluster_names, &mut errors);
+                }
+            }
+        }
+    }
+
+    for cluster in &clusters {
+        let context = cluster
+            .field("transport_socket", "transportSocket")
+            .and_then(|socket| socket.typed_config(UPSTREAM_TLS_CONTEXT_TYPE_URL));
+        for secret in context
+            .map(|context| tls_secrets(&context))
+            .unwrap_or_default()
+        {
+            check_secret(&secret, &secret_names, &mut errors);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "Invalid Envoy configuration:\n  {}",
+            errors.join("\n  ")
+        )))
+    }
+}
+
+fn nodes<'a>(
+    values: &'a [Value],
+    kind: &str,
+    locate: &impl Fn(&str, usize) -> String,
+) -> Vec<Node<'a>> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| Node {
+            value,
+            path: locate(kind, index),
+        })
+        .collect()
+}
+
+fn check_duplicate_names(kind: &str, nodes: &[Node], errors: &mut Vec<String>) {
+    let mut seen: HashMap<&str, &str> = HashMap::new();
+    for node in nodes {
+        let path = &node.path;
+        let Some(name) = node.field("name", "name").and_then(|name| name.as_str()) else {
+            continue;
+        };
+        match seen.get(name) {
+            Some(first) => errors.push(format!(
+                "{path}: duplicate {kind} name '{name}', first defined at {first}"
+            )),
+            None => {
+                seen.insert(name, path);
+            }
+        }
+    }
+}
+
+fn check_secret(secret: &Node, secret_names: &HashSet<&str>, errors: &mut Vec<String>) {
+    if let Some(name) = secret.as_str()
+        && !secret_names.contains(name)
+    {
+        errors.push(format!(
+            "{}: SDS secret '{name}' is not provided by any certificate or static certificate",
+            secret.path
+        ));
+    }
+}
+
+/// Names of the SDS certificates a filter chain's TLS or QUIC transport socket references
+fn downstream_secrets<'a>(chain: &Node<'a>) -> Vec<Node<'a>> {
+    let Some(socket) = chain.field("transport_socket", "transportSocket") else {
+        return Vec::new();
+    };
+    let context = socket
+        .typed_config(DOWNSTREAM_TLS_CONTEXT_TYPE_URL)
+        .or_else(|| {
+            socket
+                .typed_config(QUIC_DOWNSTREAM_TRANSPORT_TYPE_URL)?
+                .field("downstream_tls_context", "downstreamTlsContext")
+        });
+    context
+        .map(|context| tls_secrets(&context))
+        .unwrap_or_default()
+}
+
+/// Name nodes of a TLS context's `tls_certificate_sds_secret_configs`
+fn tls_secrets<'a>(context: &Node<'a>) -> Vec<Node<'a>> {
+    context
+        .field("common_tls_context", "commonTlsContext")
+        .map(|common| {
+            common.field_items(
+                "tls_certificate_sds_secret_configs",
+                "tlsCertificateSdsSecretConfigs",
+            )
+        })
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|config| config.field("name", "name"))
+        .collect()
+}
+
+/// Check an HCM's inline routes and, on TLS chains, its virtual host domains
+fn check_route_config(
+    hcm: &Node,
+    certificate_domains: Option<&[String]>,
+    cluster_names: &HashSet<&str>,
+    errors: &mut Vec<String>,
+) {
+    let Some(route_config) = hcm.field("route_config", "routeConfig") else {
+        return;
+    };
+
+    for virtual_host in route_config.field_items("virtual_hosts", "virtualHosts") {
+        if let Some(certificate_domains) = certificate_domains {
+            for domain in virtual_host.field_items("domains", "domains") {
+                let Some(host) = domain.as_str() else {
+                    continue;
+                };
+                if host != "*" && !certificate_domains.iter().any(|d| covers(d, host)) {
+                    errors.push(format!(
+                        "{}: domain '{host}' is not covered by the filter chain's certificates",
+                        domain.path
+                    ));
+                }
+            }
+        }
+
+        for route in virtual_host.field_items("routes", "routes") {
+            if let Some(action) = route.field("route", "route") {
+                check_cluster_references(&action, cluster_names, errors);
+            }
+        }
+    }
+}
+
+/// Check the `cluster` and `weighted_clusters` of a route action or TCP proxy
+fn check_cluster_references(node: &Node, cluster_names: &HashSet<&str>, errors: &mut Vec<String>) {
+    let weighted = node
+        .field("weighted_clusters", "weightedClusters")
+        .map(|weighted| weighted.field_items("clusters", "clusters"))
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|cluster| cluster.field("name", "name"));
+    for cluster in node.field("cluster", "cluster").into_iter().chain(weighted) {
+        if let Some(name) = cluster.as_str()
+            && !cluster_names.contains(name)
+        {
+            errors.push(format!("{}: cluster '{name}' does not exist", cluster.path));
+        }
+    }
+}
+
+/// Whether a certificate domain covers a virtual host domain, which may carry a port
+fn covers(certificate_domain: &str, host: &str) -> bool {
+    let host = match host.rsplit_once(':') {
+        Some((name, port))
+            if port.chars().all(|c| c.is_ascii_digit())
+                && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host,
+    };
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+
+    if certificate_domain == host {
+        return true;
+    }
+    // A wildcard certificate covers exactly one more label
+    certificate_domain
+        .strip_prefix("*.")
+        .and_then(|suffix| host.strip_suffix(suffix))
+        .and_then(|label| label.strip_suffix('.'))
+        .is_some_and(|label| !label.is_empty() && !label.contains('.') && label != "*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_workload() {
+        let yaml = r#"
+meta:
+  storage_dir: /tmp/test
+
+certificates:
+  - name: site
+    domains:
+      - example.com
+      - "*.example.org"
+
+envoy:
+  listeners:
+    - name: https
+      address:
+        socket_address: { address: 0.0.0.0, port_value: 443 }
+      filter_chains:
+        - transport_socket:
+            name: envoy.transport_sockets.tls
+            typed_config:
+              "@type": type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext
+              common_tls_context:
+                tls_certificate_sds_secret_configs:
+                  - name: site
+          filters:
+            - name: envoy.filters.network.http_connection_manager
+              typed_config:
+                "@type": type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager
+                route_config:
+                  virtual_hosts:
+                    - name: default
+                      domains: ["example.com:443", "www.example.org", "other.example.net"]
+                      routes:
+                        - match: { prefix: "/" }
+                          route: { cluster: backend }
+                        - match: { prefix: "/api" }
+                          route: { cluster: missing }
+    - name: acme-http
+      address:
+        socket_address: { address: 0.0.0.0, port_value: 443 }
+      filter_chains:
+        - transport_socket:
+            name: envoy.transport_sockets.tls
+            typed_config:
+              "@type": type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext
+              common_tls_context:
+                tls_certificate_sds_secret_configs:
+                  - name: unknown
+  clusters:
+    - name: backend
+    - name: backend
+      transport_socket:
+        name: envoy.transport_sockets.tls
+        typed_config:
+          "@type": type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.UpstreamTlsContext
+          common_tls_context:
+            tls_certificate_sds_secret_configs:
+              - name: client
+                sds_config:
+                  path_config_source: { path: /etc/envoy/client.yaml }
+  external_secrets: [client]
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let locate = |kind: &str, index: usize| format!("envoy.{kind}[{index}]");
+        let error = validate_workload(&config, locate).unwrap_err().to_string();
+
+        let route = "envoy.listeners[0].filter_chains[0].filters[0].typed_config.route_config.virtual_hosts[0]";
+        for expected in [
+            "envoy.clusters[1]: duplicate cluster name 'backend', first defined at envoy.clusters[0]",
+            "envoy.listeners[1].name: 'acme-http' is reserved",
+            "envoy.listeners[1].address.socket_address: TCP 0.0.0.0:443 is already bound by envoy.listeners[0].address.socket_address",
+            &format!("{route}.routes[1].route.cluster: cluster 'missing' does not exist"),
+            &format!("{route}.domains[2]: domain 'other.example.net' is not covered"),
+            "tls_certificate_sds_secret_configs[0].name: SDS secret 'unknown' is not provided",
+        ] {
+            assert!(
+                error.contains(expected),
+                "missing '{expected}' in:\n{error}"
+            );
+        }
+        assert!(
+            !error.contains("domains[0]") && !error.contains("domains[1]"),
+            "{error}"
+        );
+        assert!(!error.contains("routes[0]"), "{error}");
+        assert!(!error.contains("'client'"), "{error}");
+    }
+}
@@ -0,0 +1,283 @@
+use prost::Message;
+use xds_api::pb::envoy::config::core::v3::{
+    HeaderValue, HeaderValueOption, Http3ProtocolOptions, TransportSocket, address,
+    header_value_option::HeaderAppendAction, socket_address, transport_socket,
+};
+use xds_api::pb::envoy::config::listener::v3::{
+    FilterChain, Listener, QuicProtocolOptions, UdpListenerConfig, filter::ConfigType,
+};
+use xds_api::pb::envoy::extensions::filters::network::http_connection_manager::v3::{
+    HttpConnectionManager, http_connection_manager::CodecType,
+    http_connection_manager::RouteSpecifier,
+};
+use xds_api::pb::google::protobuf::Any;
+
+use crate::error::{Error, Result};
+
+use super::listener_port;
+
+const HTTP_CONNECTION_MANAGER_TYPE_URL: &str = "type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager";
+const DOWNSTREAM_TLS_CONTEXT_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.transport_sockets.tls.v3.DownstreamTlsContext";
+const QUIC_DOWNSTREAM_TRANSPORT_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.transport_sockets.quic.v3.QuicDownstreamTransport";
+
+/// Name of the HTTP/3 listener generated for a TLS listener
+pub fn http3_listener_name(name: &str) -> String {
+    format!("{name}-http3")
+}
+
+/// Build the UDP twin of a TLS listener, serving its filter chains over QUIC
+///
+/// Every filter chain must terminate TLS with a `DownstreamTlsContext` and consist of
+/// HTTP connection managers, which are switched to the HTTP/3 codec. The TLS context,
+/// including its SDS certificates, is reused for QUIC.
+pub fn build_http3_listener(listener: &Listener) -> Result<Listener> {
+    let error = |reason: String| {
+        Error::Config(format!(
+            "Cannot generate an HTTP/3 listener for {}: {reason}",
+            listener.name
+        ))
+    };
+
+    let mut address = listener
+        .address
+        .clone()
+        .ok_or_else(|| error("it has no address".to_string()))?;
+    match &mut address.address {
+        Some(address::Address::SocketAddress(socket_address))
+            if socket_address.protocol == socket_address::Protocol::Tcp as i32 =>
+        {
+            socket_address.protocol = socket_address::Protocol::Udp as i32;
+        }
+        _ => return Err(error("it is not a TCP socket listener".to_string())),
+    }
+
+    let filter_chains = listener
+        .filter_chains
+        .iter()
+        .enumerate()
+        .map(|(index, chain)| {
+            quic_filter_chain(chain)
+                .map_err(|reason| error(format!("filter chain {index} {reason}")))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let default_filter_chain = listener
+        .default_filter_chain
+        .as_ref()
+        .map(|chain| {
+            quic_filter_chain(chain)
+                .map_err(|reason| error(format!("default filter chain {reason}")))
+        })
+        .transpose()?;
+
+    // TCP listener filters such as tls_inspector do not apply to QUIC
+    Ok(Listener {
+        name: http3_listener_name(&listener.name),
+        address: Some(address),
+        stat_prefix: listener.stat_prefix.clone(),
+        filter_chains,
+        default_filter_chain,
+        access_log: listener.access_log.clone(),
+        udp_listener_config: Some(UdpListenerConfig {
+            quic_options: Some(QuicProtocolOptions::default()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+/// Convert a TLS filter chain to QUIC, or describe why it cannot be
+fn quic_filter_chain(chain: &FilterChain) -> std::result::Result<FilterChain, String> {
+    let tls_context = match chain
+        .transport_socket
+        .as_ref()
+        .and_then(|socket| socket.config_type.as_ref())
+    {
+        Some(transport_socket::ConfigType::TypedConfig(any))
+            if any.type_url == DOWNSTREAM_TLS_CONTEXT_TYPE_URL =>
+        {
+            &any.value
+        }
+        _ => return Err("has no DownstreamTlsContext transport socket".to_string()),
+    };
+
+    // QuicDownstreamTransport is not generated by xds-api; its only required field is
+    // the DownstreamTlsContext (field 1), so it is encoded by hand
+    let mut quic_transport = Vec::new();
+    prost::encoding::bytes::encode(1, tls_context, &mut quic_transport);
+
+    let mut chain = chain.clone();
+    chain.transport_socket = Some(TransportSocket {
+        name: "envoy.transport_sockets.quic".to_string(),
+        config_type: Some(transport_socket::ConfigType::TypedConfig(Any {
+            type_url: QUIC_DOWNSTREAM_TRANSPORT_TYPE_URL.to_string(),
+            value: quic_transport,
+        })),
+    });
+
+    if let Some(filter_chain_match) = &mut chain.filter_chain_match
+        && filter_chain_match.transport_protocol == "tls"
+    {
+        filter_chain_match.transport_protocol = "quic".to_string();
+    }
+
+    for filter in &mut chain.filters {
+        let Some(ConfigType::TypedConfig(typed_config)) = &mut filter.config_type else {
+            return Err(format!("has filter {} without a typed_config", filter.name));
+        };
+        if typed_config.type_url != HTTP_CONNECTION_MANAGER_TYPE_URL {
+            return Err(format!(
+                "has filter {} that cannot run over QUIC",
+                filter.name
+            ));
+        }
+
+        let mut hcm = HttpConnectionManager::decode(typed_config.value.as_slice())
+            .map_err(|e| format!("has an invalid HttpConnectionManager: {e}"))?;
+        hcm.codec_type = CodecType::Http3 as i32;
+        hcm.http3_protocol_options
+            .get_or_insert_with(Http3ProtocolOptions::default);
+        typed_config.value = hcm.encode_to_vec();
+    }
+
+    Ok(chain)
+}
+
+/// Advertise the HTTP/3 twin of a TLS listener via `alt-svc` response headers
+///
+/// The header is added to the inline route configuration of every TLS filter chain, unless
+/// the routes already set one.
+pub fn add_alt_svc(listener: &mut Listener, max_age_secs: u32) {
+    let Some(port) = listener_port(listener) else {
+        return;
+    };
+    let alt_svc = HeaderValueOption {
+        header: Some(HeaderValue {
+            key: "alt-svc".to_string(),
+            value: format!("h3=\":{port}\"; ma={max_age_secs}"),
+            ..Default::default()
+        }),
+        append_action: HeaderAppendAction::AddIfAbsent as i32,
+        ..Default::default()
+    };
+
+    let chains = listener
+        .filter_chains
+        .iter_mut()
+        .chain(listener.default_filter_chain.as_mut())
+        .filter(|chain| chain.transport_socket.is_some());
+    for chain in chains {
+        for filter in &mut chain.filters {
+            if let Some(ConfigType::TypedConfig(typed_config)) = &mut filter.config_type
+                && typed_config.type_url == HTTP_CONNECTION_MANAGER_TYPE_URL
+                && let Ok(mut hcm) = HttpConnectionManager::decode(typed_config.value.as_slice())
+                && let Some(RouteSpecifier::RouteConfig(route_config)) = &mut hcm.route_specifier
+            {
+                let headers = &mut route_config.response_headers_to_add;
+                if !headers.iter().any(|option| {
+                    option
+                        .header
+                        .as_ref()
+                        .is_some_and(|header| header.key.eq_ignore_ascii_case("alt-svc"))
+                }) {
+                    headers.push(alt_svc.clone());
+                }
+                typed_config.value = hcm.encode_to_vec();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::deserialize_listener;
+    use crate::envoy::is_udp_listener;
+
+    fn hcm(chain: &FilterChain) -> HttpConnectionManager {
+        let Some(ConfigType::TypedConfig(typed_config)) = &chain.filters[0].config_type else {
+            panic!("filter has no typed_config");
+        };
+        HttpConnectionManager::decode(typed_config.value.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_build_http3_listener() {
+        let mut listener = deserialize_listener(&serde_json::json!({
+            "name": "https",
+            "address": { "socket_address": { "address": "0.0.0.0", "port_value": 443 } },
+            "listener_filters": [{
+                "name": "envoy.filters.listener.tls_inspector",
+                "typed_config": {
+                    "@type": "type.googleapis.com/envoy.extensions.filters.listener.tls_inspector.v3.TlsInspector"
+                }
+            }],
+            "filter_chains": [{
+                "filter_chain_match": { "server_names": ["example.com"], "transport_protocol": "tls" },
+                "filters": [{
+                    "name": "envoy.filters.network.http_connection_manager",
+                    "typed_config": {
+                        "@type": HTTP_CONNECTION_MANAGER_TYPE_URL,
+                        "stat_prefix": "https",
+                        "route_config": { "virtual_hosts": [{ "name": "default", "domains": ["*"] }] }
+                    }
+                }],
+                "transport_socket": {
+                    "name": "envoy.transport_sockets.tls",
+                    "typed_config": {
+                        "@type": DOWNSTREAM_TLS_CONTEXT_TYPE_URL,
+                        "common_tls_context": {
+                            "tls_certificate_sds_secret_configs": [{ "name": "example" }]
+                        }
+                    }
+                }
+            }]
+        }))
+        .unwrap();
+
+        let twin = build_http3_listener(&listener).unwrap();
+        assert_eq!(twin.name, "https-http3");
+        assert!(is_udp_listener(&twin));
+        assert_eq!(listener_port(&twin), Some(443));
+        assert!(twin.listener_filters.is_empty());
+        assert!(twin.udp_listener_config.is_some());
+
+        let chain = &twin.filter_chains[0];
+        assert_eq!(
+            chain
+                .filter_chain_match
+                .as_ref()
+                .unwrap()
+                .transport_protocol,
+            "quic"
+        );
+        let Some(transport_socket::ConfigType::TypedConfig(quic)) = chain
+            .transport_socket
+            .as_ref()
+            .and_then(|socket| socket.config_type.clone())
+        else {
+            panic!("twin has no transport socket");
+        };
+        assert_eq!(quic.type_url, QUIC_DOWNSTREAM_TRANSPORT_TYPE_URL);
+        let hcm_twin = hcm(chain);
+        assert_eq!(hcm_twin.codec_type, CodecType::Http3 as i32);
+        assert!(hcm_twin.http3_protocol_options.is_some());
+
+        add_alt_svc(&mut listener, 3600);
+        let Some(RouteSpecifier::RouteConfig(route_config)) =
+            hcm(&listener.filter_chains[0]).route_specifier
+        else {
+            panic!("listener has no inline route config");
+        };
+        let header = route_config.response_headers_to_add[0]
+            .header
+            .clone()
+            .unwrap();
+        assert_eq!(header.key, "alt-svc");
+        assert_eq!(header.value, "h3=\":443\"; ma=3600");
+
+        listener.filter_chains[0].transport_socket = None;
+        assert!(build_http3_listener(&listener).is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use xds_api::pb::envoy::config::listener::v3::Listener;
 
+/// Name of the listener generated for ACME challenges when no listener is on their port
+pub const ACME_LISTENER_NAME: &str = "acme-http";
+
 /// Check if a listener is bound to a specific port
 pub fn listener_port(listener: &Listener) -> Option<u32> {
     listener
@@ -19,3 +22,18 @@ pub fn listener_port(listener: &Listener) -> Option<u32> {
             _ => None,
         })
 }
+
+/// Check if a listener accepts UDP (e.g., QUIC) rather than TCP connections
+pub fn is_udp_listener(listener: &Listener) -> bool {
+    listener
+        .address
+        .as_ref()
+        .and_then(|a| a.address.as_ref())
+        .is_some_and(|addr| match addr {
+            xds_api::pb::envoy::config::core::v3::address::Address::SocketAddress(sa) => {
+                sa.protocol
+                    == xds_api::pb::envoy::config::core::v3::socket_address::Protocol::Udp as i32
+            }
+            _ => false,
+        })
+}
@@ -1,7 +1,9 @@
+mod http3;
 mod listener;
 mod route;
 mod secret;
 
-pub use listener::listener_port;
+pub use http3::{add_alt_svc, build_http3_listener, http3_listener_name};
+pub use listener::{ACME_LISTENER_NAME, is_udp_listener, listener_port};
 pub use route::build_acme_challenge_route;
 pub use secret::build_tls_secret;
@@ -4,8 +4,13 @@ use xds_api::pb::envoy::extensions::transport_sockets::tls::v3::{
     Secret, TlsCertificate, secret::Type as SecretType,
 };
 
-/// Build a TLS secret for SDS
-pub fn build_tls_secret(name: &str, cert_chain_pem: &str, private_key_pem: &str) -> Secret {
+/// Build a TLS secret for SDS, optionally with a DER-encoded OCSP response to staple
+pub fn build_tls_secret(
+    name: &str,
+    cert_chain_pem: &str,
+    private_key_pem: &str,
+    ocsp_staple: Option<&[u8]>,
+) -> Secret {
     Secret {
         name: name.to_string(),
         r#type: Some(SecretType::TlsCertificate(TlsCertificate {
@@ -17,6 +22,10 @@ pub fn build_tls_secret(name: &str, cert_chain_pem: &str, private_key_pem: &str)
                 specifier: Some(Specifier::InlineString(private_key_pem.to_string())),
                 watched_directory: None,
             }),
+            ocsp_staple: ocsp_staple.map(|staple| DataSource {
+                specifier: Some(Specifier::InlineBytes(staple.to_vec())),
+                watched_directory: None,
+            }),
             ..Default::default()
         })),
     }
@@ -21,6 +21,13 @@ pub enum Error {
         type_url: String,
     },
 
+    #[error("Invalid typed_config for {type_url}: {source}")]
+    ConfigTypedConfig {
+        type_url: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
     #[error("ACME error: {0}")]
     Acme(#[from] instant_acme::Error),
 
@@ -47,6 +54,13 @@ pub enum Error {
     #[error("YAML parsing error: {0}")]
     Yaml(#[from] serde_yaml::Error),
 
+    #[error("YAML parsing error in {path}: {source}")]
+    YamlFile {
+        path: PathBuf,
+        #[source]
+        source: serde_yaml::Error,
+    },
+
     #[error("Systemd socket activation error: {0}")]
     SystemdSocket(#[from] sd_listen_fds::Error),
 
@@ -65,9 +79,31 @@ pub enum Error {
     #[error("Invalid X.509 timestamp")]
     X509InvalidTimestamp,
 
+    #[error("Invalid certificate: {0}")]
+    InvalidCertificate(String),
+
+    #[error("File watch error: {0}")]
+    Watch(#[from] notify::Error),
+
+    #[error("OCSP error: {0}")]
+    Ocsp(String),
+
     #[error("Challenge failed: {0}")]
     ChallengeFailed(String),
 
+    #[error("Challenge failed: {message}")]
+    ChallengeProblem {
+        message: String,
+        problem: instant_acme::Problem,
+    },
+
+    #[error("Preflight order against {directory_url} failed: {source}")]
+    Preflight {
+        directory_url: String,
+        #[source]
+        source: Box<Error>,
+    },
+
     #[error("Task join error ({task}): {source}")]
     TaskJoin {
         task: &'static str,
@@ -79,4 +115,16 @@ pub enum Error {
     ReadySignalFailed { component: &'static str },
 }
 
+impl Error {
+    /// The ACME problem document reported by the CA, if any
+    pub fn acme_problem(&self) -> Option<&instant_acme::Problem> {
+        match self {
+            Error::Acme(instant_acme::Error::Api(problem)) => Some(problem),
+            Error::ChallengeProblem { problem, .. } => Some(problem),
+            Error::Preflight { source, .. } => source.acme_problem(),
+            _ => None,
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -2,6 +2,7 @@ mod acme;
 mod config;
 mod envoy;
 mod error;
+mod reload;
 mod systemd;
 mod xds;
 
@@ -14,8 +15,12 @@ use tokio::sync::RwLock;
 use tracing::{error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use acme::{AcmeAccount, CertificateStorage, ChallengeState, RenewalManager};
+use acme::{
+    CertificateStorage, ChallengePublisher, ChallengeResponder, ChallengeState, IssuerAccounts,
+    RenewalManager, StaticCertificates,
+};
 use config::{Config, load_config};
+use reload::ConfigReloader;
 use xds::{ConfigMerger, XdsServer, XdsState};
 
 #[tokio::main]
@@ -48,13 +53,13 @@ async fn main() {
     };
 
     // Run the server
-    if let Err(e) = run(config).await {
+    if let Err(e) = run(config, config_path).await {
         error!("Server error: {}", e);
         std::process::exit(1);
     }
 }
 
-async fn run(config: Config) -> error::Result<()> {
+async fn run(config: Config, config_path: PathBuf) -> error::Result<()> {
     let socket_path_log = config
         .meta
         .socket_path
@@ -66,6 +71,7 @@ async fn run(config: Config) -> error::Result<()> {
         storage_dir = %config.meta.storage_dir.display(),
         socket_path = %socket_path_log,
         acme_directory = %config.meta.acme_directory_url,
+        num_issuers = config.all_issuers().len(),
         num_certificates = config.certificates.len(),
         "Starting envoy-acme-xds"
     );
@@ -80,9 +86,19 @@ async fn run(config: Config) -> error::Result<()> {
     // Initialize challenge state (shared between ACME and XDS)
     let challenge_state = ChallengeState::new();
 
-    // Load or create ACME account
-    let account = AcmeAccount::load_or_create(&storage, &config.meta.acme_directory_url).await?;
-    let account = Arc::new(RwLock::new(account));
+    // Answer challenges directly, independent of Envoy
+    if let Some(address) = config.meta.challenge_responder_address {
+        let responder = ChallengeResponder::bind(address, challenge_state.clone()).await?;
+        tokio::spawn(responder.run());
+    }
+
+    // Load or create an ACME account for each issuer
+    let accounts = IssuerAccounts::load_or_create(
+        &storage,
+        &config.all_issuers(),
+        &config.meta.order_rate_limit,
+    )
+    .await?;
 
     // Parse and set initial workload configuration
     let workload_listeners = ConfigMerger::parse_listeners(&config.envoy)?;
@@ -91,37 +107,56 @@ async fn run(config: Config) -> error::Result<()> {
     // Merge initial listeners (no challenges yet)
     let merged_listeners = ConfigMerger::merge_listeners(
         workload_listeners.clone(),
-        &challenge_state,
+        &[],
         config.meta.acme_challenge_port,
-    )
-    .await;
+    );
 
     xds_state.update_listeners(merged_listeners).await;
     xds_state.update_clusters(workload_clusters).await;
 
+    // Serve operator-supplied certificates before Envoy connects
+    let static_certificates = Arc::new(StaticCertificates::new(
+        config.static_certificates.clone(),
+        xds_state.clone(),
+    ));
+    static_certificates.load().await?;
+    tokio::spawn(static_certificates.clone().run(Duration::from_secs(3600)));
+
     // Create renewal manager
-    let renewal_manager = RenewalManager::new(
+    let renewal_manager = Arc::new(RenewalManager::new(
         storage.clone(),
-        account.clone(),
+        accounts,
         challenge_state.clone(),
         xds_state.clone(),
+        ChallengePublisher::new(
+            xds_state.clone(),
+            Duration::from_secs(config.meta.challenge_propagation_timeout_secs),
+            config.meta.challenge_self_check_address.clone(),
+            config.meta.challenge_responder_address.is_some(),
+        ),
         config.certificates.clone(),
-    );
+        config.meta.max_concurrent_orders,
+    ));
+
+    // Serve stored certificates, or placeholders until they are issued
+    renewal_manager.serve_initial_secrets().await;
 
     // Spawn background state updater (rebuilds listeners when challenges change)
     let state_updater_xds = xds_state.clone();
     let state_updater_challenges = challenge_state.clone();
+    // Shared with the config reloader, which replaces the workload listeners
+    let workload_listeners = Arc::new(RwLock::new(workload_listeners));
     let state_updater_workload = workload_listeners.clone();
     let state_updater_acme_port = config.meta.acme_challenge_port;
     tokio::spawn(async move {
         let mut rx = state_updater_xds.subscribe();
-        while rx.recv().await.is_ok() {
+        while XdsState::changed(&mut rx).await {
+            let (generation, challenges) = state_updater_challenges.snapshot().await;
             let merged = ConfigMerger::merge_listeners(
-                state_updater_workload.clone(),
-                &state_updater_challenges,
+                state_updater_workload.read().await.clone(),
+                &challenges,
                 state_updater_acme_port,
-            )
-            .await;
+            );
             // Update without triggering another notification (would cause loop)
             // The state update itself will bump version
             let listeners = state_updater_xds.get_listeners().await;
@@ -129,9 +164,21 @@ async fn run(config: Config) -> error::Result<()> {
                 // Only update if changed
                 state_updater_xds.update_listeners(merged).await;
             }
+            state_updater_xds.mark_challenges_applied(generation);
         }
     });
 
+    // Reload the configuration on SIGHUP and, if enabled, on file changes
+    let reloader = ConfigReloader::new(
+        config_path,
+        config.clone(),
+        workload_listeners.clone(),
+        xds_state.clone(),
+        renewal_manager.clone(),
+        static_certificates.clone(),
+    );
+    tokio::spawn(reloader.run());
+
     // Setup shutdown signal
     let shutdown = async {
         let ctrl_c = async {
@@ -195,8 +242,12 @@ async fn run(config: Config) -> error::Result<()> {
         .map_err(|_| error::Error::ReadySignalFailed {
             component: "xds server",
         })?;
-    info!("Waiting for LDS stream connection before issuing certificates");
-    xds_state.wait_for_lds().await;
+    if config.meta.challenge_responder_address.is_some() {
+        info!("Challenge responder enabled, issuing certificates without waiting for Envoy");
+    } else {
+        info!("Waiting for LDS stream connection before issuing certificates");
+        xds_state.wait_for_lds().await;
+    }
 
     // Perform initial certificate issuance
     renewal_manager.initial_issuance().await?;
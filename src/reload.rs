@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{RwLock, mpsc};
+use tracing::{debug, error, info, warn};
+use xds_api::pb::envoy::config::listener::v3::Listener;
+
+use crate::acme::{RenewalManager, StaticCertificates};
+use crate::config::{Config, config_directory, include_patterns, load_config};
+use crate::error::{Error, Result};
+use crate::xds::{ConfigMerger, XdsState};
+
+/// Delay for further file events before reloading, so editors' multi-step writes land together
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Reloads the configuration on SIGHUP or file change and applies the differences
+///
+/// Listeners, clusters and certificates are reloaded. `meta` and `issuers` are only read
+/// at startup, so a reload changing them is rejected.
+pub struct ConfigReloader {
+    config_path: PathBuf,
+    current: Config,
+    workload_listeners: Arc<RwLock<Vec<Listener>>>,
+    xds_state: Arc<XdsState>,
+    renewal_manager: Arc<RenewalManager>,
+    static_certificates: Arc<StaticCertificates>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        config_path: PathBuf,
+        current: Config,
+        workload_listeners: Arc<RwLock<Vec<Listener>>>,
+        xds_state: Arc<XdsState>,
+        renewal_manager: Arc<RenewalManager>,
+        static_certificates: Arc<StaticCertificates>,
+    ) -> Self {
+        Self {
+            config_path,
+            current,
+            workload_listeners,
+            xds_state,
+            renewal_manager,
+            static_certificates,
+        }
+    }
+
+    /// Reload whenever SIGHUP is received or, with `meta.watch_config`, the file or one of
+    /// its included fragments changes
+    pub async fn run(mut self) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let watch_tx = tx.clone();
+        let mut watcher = if self.current.meta.watch_config {
+            self.watch_or_log(&watch_tx)
+        } else {
+            None
+        };
+
+        #[cfg(unix)]
+        tokio::spawn(async move {
+            let mut hangup =
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(hangup) => hangup,
+                    Err(e) => {
+                        error!(error = %e, "Failed to install SIGHUP handler");
+                        return;
+                    }
+                };
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading configuration");
+                if tx.send(()).is_err() {
+                    return;
+                }
+            }
+        });
+
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(RELOAD_DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+
+            let include = self.current.include.clone();
+            if let Err(e) = self.reload().await {
+                error!(
+                    path = %self.config_path.display(),
+                    error = %e,
+                    "Configuration reload failed, keeping the current configuration"
+                );
+            }
+
+            // Fragments may now live in other directories
+            if watcher.is_some() && self.current.include != include {
+                watcher = self.watch_or_log(&watch_tx);
+            }
+        }
+    }
+
+    fn watch_or_log(&self, tx: &mpsc::UnboundedSender<()>) -> Option<RecommendedWatcher> {
+        match watch(&self.config_path, &self.current.include, tx.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                error!(error = %e, "Failed to watch configuration file, use SIGHUP to reload");
+                None
+            }
+        }
+    }
+
+    /// Load, validate and apply the configuration file
+    ///
+    /// Nothing is applied unless the whole configuration is valid.
+    async fn reload(&mut self) -> Result<()> {
+        let config = load_config(&self.config_path)?;
+
+        if config.meta != self.current.meta || config.issuers != self.current.issuers {
+            return Err(Error::Config(
+                "Changes to meta or issuers require a restart".to_string(),
+            ));
+        }
+
+        let listeners = ConfigMerger::parse_listeners(&config.envoy)?;
+        let clusters = ConfigMerger::parse_clusters(&config.envoy)?;
+
+        let mut workload_listeners = self.workload_listeners.write().await;
+        let listener_changes = describe_changes(&workload_listeners, &listeners, |l| &l.name);
+        if !listener_changes.is_empty() {
+            info!(changes = listener_changes.join("; "), "Listeners changed");
+            *workload_listeners = listeners;
+            // The updater merges the challenge routes into the new listeners
+            self.xds_state.notify_change();
+        }
+        drop(workload_listeners);
+
+        let current_clusters = self.xds_state.get_clusters().await;
+        let cluster_changes = describe_changes(&current_clusters, &clusters, |c| &c.name);
+        if !cluster_changes.is_empty() {
+            info!(changes = cluster_changes.join("; "), "Clusters changed");
+            self.xds_state.update_clusters(clusters).await;
+        }
+
+        if config.static_certificates != self.current.static_certificates {
+            let changes = describe_changes(
+                &self.current.static_certificates,
+                &config.static_certificates,
+                |c| &c.name,
+            );
+            info!(changes = changes.join("; "), "Static certificates changed");
+            self.static_certificates
+                .update(config.static_certificates.clone())
+                .await;
+        }
+
+        if config.certificates != self.current.certificates {
+            let changes = describe_changes(&self.current.certificates, &config.certificates, |c| {
+                &c.name
+            });
+            info!(changes = changes.join("; "), "Certificates changed");
+            // Secrets moved to static certificates were published above and must stay
+            let static_names: Vec<&str> = config
+                .static_certificates
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect();
+            let updated = self
+                .renewal_manager
+                .update_certificates(config.certificates.clone(), &static_names)
+                .await;
+            // Issuance can take minutes, so it must not hold up further reloads
+            let renewal_manager = self.renewal_manager.clone();
+            tokio::spawn(async move { renewal_manager.issue_updated(updated).await });
+        }
+
+        info!(path = %self.config_path.display(), "Configuration reloaded");
+        self.current = config;
+        Ok(())
+    }
+}
+
+/// Describe the named items added, removed and changed between two lists
+fn describe_changes<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    name: impl Fn(&T) -> &String,
+) -> Vec<String> {
+    let find = |items: &[T], wanted: &String| items.iter().position(|item| name(item) == wanted);
+    let join = |names: Vec<&String>| {
+        names
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let added: Vec<&String> = new
+        .iter()
+        .map(&name)
+        .filter(|n| find(old, n).is_none())
+        .collect();
+    let removed: Vec<&String> = old
+        .iter()
+        .map(&name)
+        .filter(|n| find(new, n).is_none())
+        .collect();
+    let changed: Vec<&String> = new
+        .iter()
+        .filter(|item| find(old, name(item)).is_some_and(|i| old[i] != **item))
+        .map(&name)
+        .collect();
+
+    let mut changes = Vec::new();
+    if !added.is_empty() {
+        changes.push(format!("added: {}", join(added)));
+    }
+    if !removed.is_empty() {
+        changes.push(format!("removed: {}", join(removed)));
+    }
+    if !changed.is_empty() {
+        changes.push(format!("changed: {}", join(changed)));
+    }
+    // Reordering alone still changes what is served
+    if changes.is_empty() && old != new {
+        changes.push("reordered".to_string());
+    }
+    changes
+}
+
+/// Watch the configuration file's directory and included fragments, and signal `tx` on
+/// changes to them
+///
+/// Directories are watched rather than files, so editors that replace files, symlink swaps
+/// (e.g., Kubernetes ConfigMaps) and added or removed fragments are seen as well.
+fn watch(
+    config_path: &Path,
+    include: &[String],
+    tx: mpsc::UnboundedSender<()>,
+) -> Result<RecommendedWatcher> {
+    let directory = config_directory(config_path);
+    let patterns = include_patterns(config_path, include)?;
+
+    // ConfigMaps update the file by swapping a "..data" symlink next to it
+    let file_name = config_path.file_name().map(|name| name.to_os_string());
+    let relevant = move |path: &Path| {
+        path.file_name().is_some_and(|name| {
+            Some(name) == file_name.as_deref() || name.to_string_lossy().starts_with("..")
+        }) || patterns.iter().any(|pattern| pattern.matches_path(path))
+    };
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event)
+                if !matches!(event.kind, EventKind::Access(_))
+                    && event.paths.iter().any(|path| relevant(path)) =>
+            {
+                debug!(paths = ?event.paths, "Configuration directory changed");
+                let _ = tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "Configuration watch error"),
+        })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    info!(directory = %directory.display(), "Watching configuration for changes");
+
+    for entry in include {
+        let (include_directory, mode) = include_watch_target(&directory, entry);
+        if include_directory == directory && mode == RecursiveMode::NonRecursive {
+            continue;
+        }
+        match watcher.watch(&include_directory, mode) {
+            Ok(()) => {
+                info!(directory = %include_directory.display(), "Watching included configuration for changes")
+            }
+            Err(e) => warn!(
+                directory = %include_directory.display(),
+                error = %e,
+                "Failed to watch included configuration, use SIGHUP to reload it"
+            ),
+        }
+    }
+
+    Ok(watcher)
+}
+
+/// Directory to watch for an include entry: the part of its path before any wildcard
+///
+/// Entries with wildcards in directory names need a recursive watch.
+fn include_watch_target(directory: &Path, entry: &str) -> (PathBuf, RecursiveMode) {
+    let path = directory.join(entry);
+    let components: Vec<_> = path.components().collect();
+    let wildcard = components.iter().position(|component| {
+        component
+            .as_os_str()
+            .to_string_lossy()
+            .contains(['*', '?', '['])
+    });
+
+    match wildcard {
+        Some(index) => {
+            let base: PathBuf = components[..index].iter().collect();
+            let mode = if index + 1 < components.len() {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            (base, mode)
+        }
+        None if path.is_dir() => (path, RecursiveMode::NonRecursive),
+        None => (config_directory(&path), RecursiveMode::NonRecursive),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_changes() {
+        let item = |name: &str, value: u32| (name.to_string(), value);
+        let old = vec![item("a", 1), item("b", 1), item("c", 1)];
+        let new = vec![item("a", 1), item("c", 2), item("d", 1)];
+
+        assert_eq!(
+            describe_changes(&old, &new, |(name, _)| name),
+            vec!["added: d", "removed: b", "changed: c"]
+        );
+        assert!(describe_changes(&old, &old, |(name, _)| name).is_empty());
+
+        let reordered = vec![item("b", 1), item("a", 1), item("c", 1)];
+        assert_eq!(
+            describe_changes(&old, &reordered, |(name, _)| name),
+            vec!["reordered"]
+        );
+    }
+}
@@ -73,7 +73,7 @@ impl ClusterDiscoveryService for CdsService {
             yield resp;
 
             // Wait for updates
-            while rx.recv().await.is_ok() {
+            while XdsState::changed(&mut rx).await {
                 let resp = Self::build_response(&state).await;
                 yield resp;
             }
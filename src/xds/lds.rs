@@ -4,7 +4,7 @@ use std::sync::Arc;
 use futures::Stream;
 use prost::Message;
 use tonic::{Request, Response, Status, Streaming};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use xds_api::pb::envoy::service::discovery::v3::{
     DeltaDiscoveryRequest, DeltaDiscoveryResponse, DiscoveryRequest, DiscoveryResponse,
 };
@@ -49,6 +49,41 @@ impl LdsService {
             ..Default::default()
         })
     }
+
+    /// Record the versions Envoy ACKs on an LDS stream until it disconnects
+    async fn track_acks(
+        state: Arc<XdsState>,
+        stream_id: u64,
+        mut requests: Streaming<DiscoveryRequest>,
+    ) {
+        while let Ok(Some(request)) = requests.message().await {
+            if let Some(version) = acked_version(&request) {
+                debug!(stream_id, version, "LDS update acknowledged");
+                state.record_lds_ack(stream_id, version);
+            } else if let Some(error) = &request.error_detail {
+                warn!(
+                    stream_id,
+                    accepted_version = request.version_info,
+                    error = error.message,
+                    "Envoy rejected LDS update"
+                );
+            }
+        }
+
+        debug!(stream_id, "LDS stream closed");
+        state.unregister_lds_stream(stream_id);
+    }
+}
+
+/// Listener version an LDS request acknowledges
+///
+/// `None` for the initial request and for NACKs, whose version is the last accepted one
+/// rather than the rejected update.
+fn acked_version(request: &DiscoveryRequest) -> Option<u64> {
+    if request.error_detail.is_some() {
+        return None;
+    }
+    request.version_info.parse().ok()
 }
 
 type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
@@ -60,11 +95,18 @@ impl ListenerDiscoveryService for LdsService {
 
     async fn stream_listeners(
         &self,
-        _request: Request<Streaming<DiscoveryRequest>>,
+        request: Request<Streaming<DiscoveryRequest>>,
     ) -> Result<Response<Self::StreamListenersStream>, Status> {
         info!("New LDS stream connection");
         self.state.mark_lds_connected();
 
+        let stream_id = self.state.register_lds_stream();
+        tokio::spawn(Self::track_acks(
+            self.state.clone(),
+            stream_id,
+            request.into_inner(),
+        ));
+
         let state = self.state.clone();
         let mut rx = state.subscribe();
 
@@ -74,7 +116,7 @@ impl ListenerDiscoveryService for LdsService {
             yield resp;
 
             // Wait for updates
-            while rx.recv().await.is_ok() {
+            while XdsState::changed(&mut rx).await {
                 let resp = Self::build_response(&state).await;
                 yield resp;
             }
@@ -97,3 +139,32 @@ impl ListenerDiscoveryService for LdsService {
         Self::build_response(&self.state).await.map(Response::new)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use xds_api::pb::google::rpc::Status as RpcStatus;
+
+    use super::*;
+
+    #[test]
+    fn test_acked_version() {
+        let initial = DiscoveryRequest::default();
+        assert_eq!(acked_version(&initial), None);
+
+        let ack = DiscoveryRequest {
+            version_info: "3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(acked_version(&ack), Some(3));
+
+        let nack = DiscoveryRequest {
+            version_info: "3".to_string(),
+            error_detail: Some(RpcStatus {
+                message: "duplicate listener".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(acked_version(&nack), None);
+    }
+}
@@ -10,10 +10,13 @@ use xds_api::pb::envoy::extensions::filters::network::http_connection_manager::v
 };
 use xds_api::pb::google::protobuf::Any;
 
-use crate::acme::ChallengeState;
-use crate::config::{EnvoyWorkloadConfig, deserialize_clusters, deserialize_listener};
-use crate::envoy::{build_acme_challenge_route, listener_port};
-use crate::error::Result;
+use crate::acme::ActiveChallenge;
+use crate::config::{EnvoyWorkloadConfig, Http3Config, deserialize_clusters, deserialize_listener};
+use crate::envoy::{
+    ACME_LISTENER_NAME, add_alt_svc, build_acme_challenge_route, build_http3_listener,
+    http3_listener_name, is_udp_listener, listener_port,
+};
+use crate::error::{Error, Result};
 
 const HTTP_CONNECTION_MANAGER_TYPE_URL: &str = "type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager";
 const ROUTER_TYPE_URL: &str = "type.googleapis.com/envoy.extensions.filters.http.router.v3.Router";
@@ -22,9 +25,46 @@ const ROUTER_TYPE_URL: &str = "type.googleapis.com/envoy.extensions.filters.http
 pub struct ConfigMerger;
 
 impl ConfigMerger {
-    /// Parse workload listeners from JSON values
+    /// Parse workload listeners from JSON values, adding any HTTP/3 twins
     pub fn parse_listeners(config: &EnvoyWorkloadConfig) -> Result<Vec<Listener>> {
-        config.listeners.iter().map(deserialize_listener).collect()
+        let mut listeners = config
+            .listeners
+            .iter()
+            .map(deserialize_listener)
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(http3) = &config.http3 {
+            Self::add_http3_listeners(&mut listeners, http3)?;
+        }
+
+        Ok(listeners)
+    }
+
+    /// Generate a QUIC listener for each configured TLS listener and advertise it
+    fn add_http3_listeners(listeners: &mut Vec<Listener>, http3: &Http3Config) -> Result<()> {
+        let mut twins = Vec::new();
+        for name in &http3.listeners {
+            let twin_name = http3_listener_name(name);
+            if listeners.iter().any(|listener| listener.name == twin_name) {
+                return Err(Error::Config(format!(
+                    "Cannot generate HTTP/3 listener {twin_name}: a listener with that name exists"
+                )));
+            }
+
+            let listener = listeners
+                .iter_mut()
+                .find(|listener| &listener.name == name)
+                .ok_or_else(|| {
+                    Error::Config(format!(
+                        "HTTP/3 listener {name} is not a configured listener"
+                    ))
+                })?;
+            twins.push(build_http3_listener(listener)?);
+            add_alt_svc(listener, http3.alt_svc_max_age_secs);
+        }
+
+        listeners.append(&mut twins);
+        Ok(())
     }
 
     /// Parse workload clusters from JSON values
@@ -33,13 +73,11 @@ impl ConfigMerger {
     }
 
     /// Merge ACME challenge routes into listeners
-    pub async fn merge_listeners(
+    pub fn merge_listeners(
         workload_listeners: Vec<Listener>,
-        challenge_state: &ChallengeState,
+        challenges: &[ActiveChallenge],
         acme_challenge_port: u16,
     ) -> Vec<Listener> {
-        let challenges = challenge_state.get_all().await;
-
         if challenges.is_empty() {
             return workload_listeners;
         }
@@ -55,24 +93,25 @@ impl ConfigMerger {
             "Merging ACME challenge routes"
         );
 
-        // Find HTTP listener on configured ACME challenge port or create one
+        // Add routes to every TCP listener on the configured ACME challenge port, so listeners
+        // bound to specific IP addresses serve challenges for them, or create one
         let mut listeners = workload_listeners;
-        let acme_port_idx = listeners
-            .iter()
-            .position(|l| listener_port(l) == Some(acme_challenge_port as u32));
-
-        match acme_port_idx {
-            Some(idx) => {
-                // Prepend ACME routes to existing listener
-                listeners[idx] = Self::prepend_routes_to_listener(&listeners[idx], acme_routes);
-            }
-            None => {
-                // Create new listener on configured port for ACME challenges
-                let acme_listener = Self::create_acme_listener(acme_routes, acme_challenge_port);
-                listeners.push(acme_listener);
+        let mut found = false;
+        for listener in &mut listeners {
+            if listener_port(listener) == Some(acme_challenge_port as u32)
+                && !is_udp_listener(listener)
+            {
+                *listener = Self::prepend_routes_to_listener(listener, acme_routes.clone());
+                found = true;
             }
         }
 
+        if !found {
+            // Create new listener on configured port for ACME challenges
+            let acme_listener = Self::create_acme_listener(acme_routes, acme_challenge_port);
+            listeners.push(acme_listener);
+        }
+
         listeners
     }
 
@@ -163,7 +202,7 @@ impl ConfigMerger {
         };
 
         Listener {
-            name: "acme-http".to_string(),
+            name: ACME_LISTENER_NAME.to_string(),
             address: Some(Address {
                 address: Some(
                     xds_api::pb::envoy::config::core::v3::address::Address::SocketAddress(
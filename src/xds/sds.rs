@@ -94,7 +94,7 @@ impl SecretDiscoveryService for SdsService {
             yield resp;
 
             // Wait for updates
-            while rx.recv().await.is_ok() {
+            while XdsState::changed(&mut rx).await {
                 let resp = Self::build_response(&state, &resource_names).await;
                 yield resp;
             }
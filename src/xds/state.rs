@@ -1,12 +1,18 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
-use tokio::sync::{Notify, RwLock, broadcast};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Notify, RwLock, broadcast, watch};
+use tokio::time::Instant;
 use tracing::debug;
 use xds_api::pb::envoy::config::cluster::v3::Cluster;
+use xds_api::pb::envoy::config::core::v3::DataSource;
+use xds_api::pb::envoy::config::core::v3::data_source::Specifier;
 use xds_api::pb::envoy::config::listener::v3::Listener;
-use xds_api::pb::envoy::extensions::transport_sockets::tls::v3::Secret;
+use xds_api::pb::envoy::extensions::transport_sockets::tls::v3::{
+    Secret, secret::Type as SecretType,
+};
 
 use crate::envoy::build_tls_secret;
 
@@ -26,6 +32,34 @@ pub struct XdsState {
     lds_connected: AtomicBool,
     /// Notify waiters when LDS connects
     lds_notify: Notify,
+    /// Which challenge set the served listeners contain and what LDS streams have ACKed
+    propagation: watch::Sender<ListenerPropagation>,
+    /// Identifier for the next LDS stream
+    next_lds_stream: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct ListenerPropagation {
+    /// Version of the most recent listener update
+    listener_version: u64,
+    /// Latest challenge generation merged into the listeners
+    challenge_generation: u64,
+    /// Listener version that carries `challenge_generation`
+    challenge_version: u64,
+    /// Last version ACKed by each connected LDS stream
+    lds_acks: HashMap<u64, u64>,
+}
+
+impl ListenerPropagation {
+    /// Whether every connected LDS stream has ACKed listeners containing `generation`
+    fn serves(&self, generation: u64) -> bool {
+        self.challenge_generation >= generation
+            && !self.lds_acks.is_empty()
+            && self
+                .lds_acks
+                .values()
+                .all(|version| *version >= self.challenge_version)
+    }
 }
 
 impl XdsState {
@@ -39,6 +73,8 @@ impl XdsState {
             notify,
             lds_connected: AtomicBool::new(false),
             lds_notify: Notify::new(),
+            propagation: watch::Sender::new(ListenerPropagation::default()),
+            next_lds_stream: AtomicU64::new(0),
         })
     }
 
@@ -62,6 +98,21 @@ impl XdsState {
         self.notify.subscribe()
     }
 
+    /// Wait for the next change notification on a subscription
+    ///
+    /// Returns `false` once the state is dropped. A lagging receiver has missed
+    /// notifications, which still means the state changed, so it is not treated as an error.
+    pub async fn changed(rx: &mut broadcast::Receiver<u64>) -> bool {
+        match rx.recv().await {
+            Ok(_) => true,
+            Err(RecvError::Lagged(skipped)) => {
+                debug!(skipped, "Subscriber lagged behind state changes");
+                true
+            }
+            Err(RecvError::Closed) => false,
+        }
+    }
+
     /// Notify subscribers of a change (without bumping version)
     /// Used when challenges change but we want to trigger a rebuild
     pub fn notify_change(&self) {
@@ -84,12 +135,72 @@ impl XdsState {
         notified.await;
     }
 
+    /// Register a connected LDS stream for ACK tracking and return its identifier
+    pub fn register_lds_stream(&self) -> u64 {
+        let id = self.next_lds_stream.fetch_add(1, Ordering::SeqCst);
+        self.propagation.send_modify(|p| {
+            p.lds_acks.insert(id, 0);
+        });
+        id
+    }
+
+    /// Number of LDS streams currently connected
+    pub fn lds_stream_count(&self) -> usize {
+        self.propagation.borrow().lds_acks.len()
+    }
+
+    /// Record the version an LDS stream has ACKed
+    pub fn record_lds_ack(&self, id: u64, version: u64) {
+        self.propagation
+            .send_if_modified(|p| match p.lds_acks.get_mut(&id) {
+                Some(acked) if *acked != version => {
+                    *acked = version;
+                    true
+                }
+                _ => false,
+            });
+    }
+
+    /// Stop tracking a disconnected LDS stream
+    pub fn unregister_lds_stream(&self, id: u64) {
+        self.propagation.send_modify(|p| {
+            p.lds_acks.remove(&id);
+        });
+    }
+
+    /// Record that the current listeners contain the challenge set of `generation`
+    pub fn mark_challenges_applied(&self, generation: u64) {
+        self.propagation.send_if_modified(|p| {
+            if generation <= p.challenge_generation {
+                return false;
+            }
+            p.challenge_generation = generation;
+            p.challenge_version = p.listener_version;
+            true
+        });
+    }
+
+    /// Wait until every connected LDS stream has ACKed listeners containing the challenge
+    /// set of `generation`, returning the listener version that carries it
+    ///
+    /// Returns `None` if that does not happen before `deadline`.
+    pub async fn wait_for_challenge_ack(&self, generation: u64, deadline: Instant) -> Option<u64> {
+        let mut rx = self.propagation.subscribe();
+        let propagation = tokio::time::timeout_at(deadline, rx.wait_for(|p| p.serves(generation)))
+            .await
+            .ok()?
+            .ok()?;
+        Some(propagation.challenge_version)
+    }
+
     /// Update listeners and bump version
     pub async fn update_listeners(&self, listeners: Vec<Listener>) {
         let mut state = self.listeners.write().await;
         *state = listeners;
         drop(state);
-        self.bump_version().await;
+        let version = self.bump_version().await;
+        self.propagation
+            .send_modify(|p| p.listener_version = version);
     }
 
     /// Update clusters and bump version
@@ -102,13 +213,46 @@ impl XdsState {
 
     /// Update a single secret and bump version
     pub async fn update_secret(&self, name: &str, cert_chain_pem: String, private_key_pem: String) {
-        let secret = build_tls_secret(name, &cert_chain_pem, &private_key_pem);
+        let secret = build_tls_secret(name, &cert_chain_pem, &private_key_pem, None);
         let mut secrets = self.secrets.write().await;
         secrets.insert(name.to_string(), secret);
         drop(secrets);
         self.bump_version().await;
     }
 
+    /// Remove a secret and bump version if it existed
+    pub async fn remove_secret(&self, name: &str) {
+        let removed = self.secrets.write().await.remove(name).is_some();
+        if removed {
+            self.bump_version().await;
+        }
+    }
+
+    /// Set or clear the OCSP staple of an existing TLS secret
+    ///
+    /// Only bumps the version if the staple actually changed.
+    pub async fn update_ocsp_staple(&self, name: &str, staple: Option<Vec<u8>>) {
+        let mut secrets = self.secrets.write().await;
+        let Some(Secret {
+            r#type: Some(SecretType::TlsCertificate(tls_certificate)),
+            ..
+        }) = secrets.get_mut(name)
+        else {
+            return;
+        };
+
+        let staple = staple.map(|staple| DataSource {
+            specifier: Some(Specifier::InlineBytes(staple)),
+            watched_directory: None,
+        });
+        if tls_certificate.ocsp_staple == staple {
+            return;
+        }
+        tls_certificate.ocsp_staple = staple;
+        drop(secrets);
+        self.bump_version().await;
+    }
+
     /// Get all current listeners
     pub async fn get_listeners(&self) -> Vec<Listener> {
         self.listeners.read().await.clone()
@@ -136,6 +280,93 @@ impl Default for XdsState {
             notify,
             lds_connected: AtomicBool::new(false),
             lds_notify: Notify::new(),
+            propagation: watch::Sender::new(ListenerPropagation::default()),
+            next_lds_stream: AtomicU64::new(0),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_millis(50)
+    }
+
+    async fn current_version(state: &XdsState) -> u64 {
+        state.version_info().await.parse().unwrap()
+    }
+
+    #[test]
+    fn test_serves() {
+        let mut propagation = ListenerPropagation {
+            listener_version: 3,
+            challenge_generation: 1,
+            challenge_version: 3,
+            lds_acks: HashMap::new(),
+        };
+        // Without a connected stream nothing is served
+        assert!(!propagation.serves(1));
+
+        propagation.lds_acks.insert(0, 2);
+        assert!(!propagation.serves(1));
+        propagation.lds_acks.insert(0, 3);
+        assert!(propagation.serves(1));
+        assert!(!propagation.serves(2));
+
+        propagation.lds_acks.insert(1, 2);
+        assert!(!propagation.serves(1));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_challenge_ack() {
+        let state = XdsState::new();
+
+        state.update_listeners(Vec::new()).await;
+        state.mark_challenges_applied(1);
+        assert_eq!(state.wait_for_challenge_ack(1, deadline()).await, None);
+
+        // A stale ACK, or a NACK that records nothing, does not release the wait
+        let stream = state.register_lds_stream();
+        state.record_lds_ack(stream, current_version(&state).await - 1);
+        assert_eq!(state.wait_for_challenge_ack(1, deadline()).await, None);
+
+        // Generation applied before the ACK
+        let version = current_version(&state).await;
+        let wait = tokio::spawn({
+            let state = state.clone();
+            async move { state.wait_for_challenge_ack(1, deadline()).await }
+        });
+        tokio::task::yield_now().await;
+        state.record_lds_ack(stream, version);
+        assert_eq!(wait.await.unwrap(), Some(version));
+
+        // Generation applied after Envoy ACKed the listeners carrying it
+        state.update_listeners(Vec::new()).await;
+        let version = current_version(&state).await;
+        state.record_lds_ack(stream, version);
+        assert_eq!(state.wait_for_challenge_ack(2, deadline()).await, None);
+        state.mark_challenges_applied(2);
+        assert_eq!(
+            state.wait_for_challenge_ack(2, deadline()).await,
+            Some(version)
+        );
+
+        // A lagging stream disconnecting releases the wait
+        let lagging = state.register_lds_stream();
+        state.update_listeners(Vec::new()).await;
+        state.mark_challenges_applied(3);
+        let version = current_version(&state).await;
+        state.record_lds_ack(stream, version);
+        let wait = tokio::spawn({
+            let state = state.clone();
+            async move { state.wait_for_challenge_ack(3, deadline()).await }
+        });
+        tokio::task::yield_now().await;
+        state.unregister_lds_stream(lagging);
+        assert_eq!(wait.await.unwrap(), Some(version));
+    }
+}
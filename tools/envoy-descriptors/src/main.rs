@@ -0,0 +1,96 @@
+//! Compiles the Envoy API protos into `src/config/envoy-descriptors.bin`
+//!
+//! The proto root is laid out as in the `envoy-types` crate's `proto` directory, which
+//! bundles `data-plane-api` together with its dependencies. The bundled descriptors are
+//! generated from `envoy-types` 0.6.1:
+//!
+//! ```sh
+//! cargo run --release -- ~/.cargo/registry/src/*/envoy-types-0.6.1/proto \
+//!     ../../src/config/envoy-descriptors.bin
+//! ```
+//!
+//! Every Envoy proto must compile, so the descriptors never silently lack a type.
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use prost::Message;
+use prost_types::FileDescriptorSet;
+
+/// Import roots below the proto root, in lookup order
+const INCLUDES: &[&str] = &[
+    "data-plane-api",
+    "xds",
+    "protoc-gen-validate",
+    "googleapis",
+    "opencensus-proto/src",
+    "client_model",
+    "cel-spec/proto",
+    "opentelemetry-proto",
+];
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <proto root> <output>", args[0]);
+        return ExitCode::FAILURE;
+    }
+    let root = PathBuf::from(&args[1]);
+    let includes: Vec<PathBuf> = INCLUDES
+        .iter()
+        .map(|include| root.join(include))
+        .filter(|path| path.exists())
+        .collect();
+
+    let api_root = root.join("data-plane-api");
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(api_root.join("envoy")) {
+        let entry = entry.expect("failed to walk proto root");
+        if entry.path().extension().is_some_and(|ext| ext == "proto") {
+            files.push(entry.path().strip_prefix(&api_root).unwrap().to_path_buf());
+        }
+    }
+    files.sort();
+
+    // Each file is compiled on its own: protox resolves relative type names against
+    // everything in a compiler, which mis-resolves some of Envoy's references
+    let mut compiled = BTreeMap::new();
+    let mut order = Vec::new();
+    let mut failed = 0;
+    for file in &files {
+        let mut compiler = protox::Compiler::new(&includes).expect("invalid include paths");
+        compiler.include_source_info(false).include_imports(true);
+        if let Err(e) = compiler.open_file(file) {
+            eprintln!("Failed to compile {}: {e}", file.display());
+            failed += 1;
+            continue;
+        }
+        for descriptor in compiler.file_descriptor_set().file {
+            let name = descriptor.name().to_string();
+            if let Entry::Vacant(entry) = compiled.entry(name) {
+                order.push(entry.key().clone());
+                entry.insert(descriptor);
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{failed} of {} files failed to compile", files.len());
+        return ExitCode::FAILURE;
+    }
+
+    // Dependencies come before their dependents, as the descriptor pool requires
+    let set = FileDescriptorSet {
+        file: order
+            .iter()
+            .map(|name| compiled.remove(name).unwrap())
+            .collect(),
+    };
+    if let Err(e) = std::fs::write(&args[2], set.encode_to_vec()) {
+        eprintln!("Failed to write {}: {e}", args[2]);
+        return ExitCode::FAILURE;
+    }
+    eprintln!("Wrote {} files to {}", set.file.len(), args[2]);
+    ExitCode::SUCCESS
+}